@@ -0,0 +1,74 @@
+//! Timings of the hot paths of the engine, run with `cargo bench`. Each
+//! benchmark is repeated for about a second and reported per iteration.
+
+use martian_engine::*;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    // warm up, then time batches until a second has passed
+    for _ in 0..10 {
+        black_box(f());
+    }
+
+    let start = Instant::now();
+    let mut iters = 0u64;
+
+    let mut batch = 1;
+
+    // grow the batches so that reading the clock costs little
+    while start.elapsed() < Duration::from_secs(1) {
+        for _ in 0..batch {
+            black_box(f());
+        }
+        iters += batch;
+        batch = (batch * 2).min(1 << 16);
+    }
+
+    let ns = start.elapsed().as_nanos() / iters as u128;
+
+    println!("{:24} {:>12} ns/iter  ({} iterations)", name, ns, iters);
+}
+
+fn main() {
+    let tables = &TABLES;
+    let start = Board::new();
+    let middle = Board(0xea9020804b100000);
+    let mut moves = Vec::new();
+
+    bench("pieces_value", || black_box(&start).pieces_value());
+    bench("player_value", || black_box(&start).player_value());
+
+    // attacks from every square with a middlegame's blockers
+    let occ = middle.occ();
+
+    bench("drone_attacks x32", || {
+        (0..32).fold(0, |acc, sq| acc ^ tables.attacks(1, sq, black_box(occ)))
+    });
+
+    bench("queen_attacks x32", || {
+        (0..32).fold(0, |acc, sq| acc ^ tables.attacks(2, sq, black_box(occ)))
+    });
+
+    bench("gen_moves", || {
+        black_box(&middle).gen_moves(false, Board(0), tables, &mut moves);
+        moves.clone()
+    });
+
+    bench("gen_takes", || {
+        black_box(&middle).gen_takes(false, tables, &mut moves);
+        moves.clone()
+    });
+
+    let position = Position::new(tables);
+
+    bench("eval", || black_box(&position).eval());
+
+    bench("search depth 5", || {
+        let mut searcher = Searcher::new(position.clone());
+
+        searcher.set_hash_entries(1 << 16);
+        searcher.search(&Limits::depth(5))
+    });
+}
@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/variant.rs"]
+#[allow(dead_code)]
+mod variant;
+#[path = "src/magic.rs"]
+#[allow(dead_code)]
+mod magic;
+
+use variant::Variant;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/magic.rs");
+    println!("cargo:rerun-if-changed=src/variant.rs");
+
+    let out = Path::new(&env::var_os("OUT_DIR").unwrap()).join("tables.rs");
+
+    let variant = Variant::standard();
+    let source = if env::var_os("CARGO_FEATURE_RAYS").is_some() {
+        magic::rays_source(&variant)
+    } else {
+        magic::tables_source(&variant)
+    };
+
+    fs::write(out, source).unwrap();
+}
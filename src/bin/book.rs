@@ -0,0 +1,206 @@
+use martian_engine::*;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "\
+usage: book build OUTPUT [options]
+       book dump BOOK [options]
+
+build adds the opening moves of recorded and self-played games to a book and
+writes it to OUTPUT. dump prints the moves of the book from a position down,
+with their weights and the wins, draws and losses of the player moving.
+
+build options:
+    --book FILE        add to the book in FILE rather than an empty one
+    --games FILE       games to add, one per line, as recorded by selfplay
+    --selfplay N       games to play from the start position (default 0)
+    --time MS          time per move in self-play
+    --nodes N          nodes per move in self-play
+    --depth N          depth per move in self-play (default 4)
+    --skill N          skill level in self-play, lower levels varying their
+                       moves more (default 8)
+    --params FILE      evaluation weights in self-play
+    --plies N          moves of each game added (default 16)
+    --max-moves N      moves before a game is decided on points (default 200)
+
+dump options:
+    --fen POSITION     start from POSITION (default: the start position)
+    --plies N          moves to follow down from it (default 4)";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn parse<T: std::str::FromStr>(s: String) -> T {
+    s.parse().unwrap_or_else(|_| fail(USAGE))
+}
+
+fn load_book(path: &str) -> Book {
+    Book::load(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let command = args.next().unwrap_or_else(|| fail(USAGE));
+    let path = args.next().unwrap_or_else(|| fail(USAGE));
+
+    match command.as_str() {
+        "build" => build(&path, args),
+        "dump" => dump(&path, args),
+        _ => fail(USAGE)
+    }
+}
+
+fn build(output: &str, mut args: impl Iterator<Item = String>) {
+    let tables = &TABLES;
+    let mut book = Book::new();
+    let mut game_paths = Vec::new();
+    let mut selfplay = 0;
+    let mut limits = Limits::depth(4);
+    let mut engine = EngineConfig::new("book");
+    let mut plies = 16;
+    let mut max_moves = 200;
+
+    engine.skill = 8;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+
+        match arg.as_str() {
+            "--book"      => book = load_book(&value()),
+            "--games"     => game_paths.push(value()),
+            "--selfplay"  => selfplay = parse(value()),
+            "--time"      => limits = Limits::time(parse(value())),
+            "--nodes"     => limits = Limits::nodes(parse(value())),
+            "--depth"     => limits = Limits::depth(parse(value())),
+            "--skill"     => engine.skill = parse(value()),
+            "--params"    => {
+                let path = value();
+
+                engine.params = EvalParams::load(&path)
+                    .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            }
+            "--plies"     => plies = parse(value()),
+            "--max-moves" => max_moves = parse(value()),
+            _ => fail(USAGE)
+        }
+    }
+
+    for path in game_paths.iter() {
+        let text = fs::read_to_string(path)
+            .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        let mut added = 0;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match Game::parse(line, tables) {
+                Some(game) => {
+                    book.add_game(&game, plies);
+                    added += 1;
+                }
+                None => eprintln!("{}:{}: skipping invalid line", path, i + 1),
+            }
+        }
+
+        println!("added {} games from {}", added, path);
+    }
+
+    let start = Position::new(tables);
+    let mut p1 = engine.searcher(start.clone());
+    let mut p2 = engine.searcher(start.clone());
+
+    for i in 0..selfplay {
+        let game = play_game(start.clone(), [&mut p1, &mut p2], &limits, max_moves);
+        let [s1, s2] = game.points();
+
+        book.add_game(&game, plies);
+
+        println!("game {:4}  {} ({}-{})  {} positions",
+                 i + 1, result_string(game.outcome()), s1, s2, book.len());
+    }
+
+    book.save(output)
+        .unwrap_or_else(|e| fail(&format!("{}: {}", output, e)));
+
+    println!("wrote {} positions to {}", book.len(), output);
+}
+
+fn dump(path: &str, mut args: impl Iterator<Item = String>) {
+    let tables = &TABLES;
+    let book = load_book(path);
+    let mut pos = Position::new(tables);
+    let mut plies = 4;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+
+        match arg.as_str() {
+            "--fen"   => {
+                let s = value();
+                let game = Game::parse(&s, tables)
+                    .unwrap_or_else(|| fail(&format!("bad position: {}", s)));
+
+                pos = game.position().clone();
+            }
+            "--plies" => plies = parse(value()),
+            _ => fail(USAGE)
+        }
+    }
+
+    let mut seen = HashSet::new();
+
+    println!("{}", pos.to_fen());
+    print_moves(&book, &mut pos, plies, 0, &mut seen);
+    println!("{} of {} positions shown", seen.len(), book.len());
+}
+
+/// Prints the book moves of `pos` and, indented below each, those of the
+/// position it leads to, `plies` moves deep.
+fn print_moves(book: &Book,
+               pos: &mut Position,
+               plies: usize,
+               indent: usize,
+               seen: &mut HashSet<u64>)
+{
+    if plies == 0 || book.moves(pos).is_empty() {
+        return;
+    }
+
+    seen.insert(pos.key());
+
+    let mut moves = book.moves(pos).to_vec();
+    let mut legal = Vec::new();
+
+    moves.sort_by_key(|m| std::cmp::Reverse(m.weight));
+    pos.gen_moves(&mut legal);
+
+    for m in moves {
+        // an entry that isn't a legal move, from a damaged file or another
+        // position with the same key, is shown as the board it leads to
+        if !legal.contains(&m.board) {
+            println!("{:indent$}{:016x}  weight {:5}  +{} ={} -{}  (not legal here)",
+                     "", m.board.0, m.weight,
+                     m.wins, m.draws, m.losses, indent = indent);
+            continue;
+        }
+
+        let u = pos.do_move(m.board);
+
+        println!("{:indent$}{}  weight {:5}  +{} ={} -{}",
+                 "", stringify_move(pos.get_move()), m.weight,
+                 m.wins, m.draws, m.losses, indent = indent);
+
+        print_moves(book, pos, plies - 1, indent + 2, seen);
+        pos.undo_move(u);
+    }
+}
@@ -0,0 +1,184 @@
+use martian_engine::*;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "\
+usage: selfplay [options]
+
+Plays a match between two engine configurations, A and B, and reports the
+Elo difference of A over B.
+
+options:
+    --games N          games to play, two per opening (default 100)
+    --time MS          time per move in milliseconds (default 100)
+    --nodes N          nodes per move instead of a time limit
+    --depth N          depth per move instead of a time limit
+    --params-a FILE    evaluation weights of A
+    --params-b FILE    evaluation weights of B
+    --skill-a N        skill level of A (default 10)
+    --skill-b N        skill level of B (default 10)
+    --hash MB          transposition table size of each engine (default 16)
+    --openings FILE    starting positions, one per line (default: random)
+    --plies N          random moves in generated openings (default 4)
+    --max-moves N      moves before a game is decided on points (default 200)
+    --threads N        games played at once (default: all cores)
+    --sprt E0,E1       stop early once A is shown to be E0 or E1 Elo better
+    --record FILE      write every game to FILE
+    --positions FILE   write every position with its game result to FILE,
+                       in the format read by tune";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn parse<T: std::str::FromStr>(s: String) -> T {
+    s.parse().unwrap_or_else(|_| fail(USAGE))
+}
+
+fn load_params(path: &str) -> EvalParams {
+    EvalParams::load(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn create(path: &str) -> BufWriter<File> {
+    BufWriter::new(
+        File::create(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+    )
+}
+
+fn main() {
+    let mut a = EngineConfig::new("A");
+    let mut b = EngineConfig::new("B");
+    let mut config = MatchConfig::default();
+    let mut games: usize = 100;
+    let mut plies = 4;
+    let mut openings_path = None;
+    let mut record = None;
+    let mut positions = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+
+        match arg.as_str() {
+            "--games"     => games = parse(value()),
+            "--time"      => config.limits = Limits::time(parse(value())),
+            "--nodes"     => config.limits = Limits::nodes(parse(value())),
+            "--depth"     => config.limits = Limits::depth(parse(value())),
+            "--params-a"  => a.params = load_params(&value()),
+            "--params-b"  => b.params = load_params(&value()),
+            "--skill-a"   => a.skill = parse(value()),
+            "--skill-b"   => b.skill = parse(value()),
+            "--hash"      => {
+                let hash = parse(value());
+                a.hash = hash;
+                b.hash = hash;
+            }
+            "--openings"  => openings_path = Some(value()),
+            "--plies"     => plies = parse(value()),
+            "--max-moves" => config.max_moves = parse(value()),
+            "--threads"   => config.threads = parse(value()),
+            "--sprt" => {
+                let v = value();
+                let mut elos = v.split(',').map(|e| parse::<f64>(e.to_string()));
+
+                match (elos.next(), elos.next()) {
+                    (Some(e0), Some(e1)) => config.sprt = Some(Sprt::new(e0, e1)),
+                    _ => fail(USAGE)
+                }
+            }
+            "--record"    => record = Some(create(&value())),
+            "--positions" => positions = Some(create(&value())),
+            _ => fail(USAGE)
+        }
+    }
+
+    let tables = &TABLES;
+
+    let openings = match openings_path {
+        Some(path) => {
+            let text = fs::read_to_string(&path)
+                .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+
+            let mut out = Vec::new();
+
+            for (i, line) in text.lines().enumerate() {
+                let line = line.trim();
+
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                match Position::parse_fen(line, tables) {
+                    Some(pos) => out.push(pos),
+                    None => eprintln!("{}:{}: skipping invalid line", path, i + 1),
+                }
+            }
+
+            out
+        }
+        None => random_openings(tables, games.div_ceil(2), plies),
+    };
+
+    let stats = run_match(&openings, &a, &b, &config, games, |result, stats| {
+        let game = &result.game;
+        let [p1, p2] = game.points();
+        let (elo, margin) = stats.elo();
+
+        let names = if result.a_first {
+            (&a.name, &b.name)
+        } else {
+            (&b.name, &a.name)
+        };
+
+        print!("game {:4}  {} vs {}  {} ({}-{})  +{} ={} -{}  elo {:.1} +/- {:.1}",
+               result.index + 1, names.0, names.1,
+               result_string(game.outcome()), p1, p2,
+               stats.wins, stats.draws, stats.losses, elo, margin);
+
+        if let Some(sprt) = &config.sprt {
+            let (lower, upper) = sprt.bounds();
+            print!("  llr {:.2} ({:.2}, {:.2})", stats.llr(sprt), lower, upper);
+        }
+
+        println!();
+
+        if let Some(out) = &mut record {
+            writeln!(out, "# game {}: {} vs {}", result.index + 1, names.0, names.1)
+                .and_then(|_| writeln!(out, "{}", game.to_record()))
+                .unwrap_or_else(|e| fail(&e.to_string()));
+        }
+
+        if let Some(out) = &mut positions {
+            let result = result_string(game.outcome());
+
+            for pos in game.positions().iter().filter(|p| !p.game_end()) {
+                writeln!(out, "{} {}", pos.to_fen(), result)
+                    .unwrap_or_else(|e| fail(&e.to_string()));
+            }
+        }
+    });
+
+    for out in record.iter_mut().chain(positions.iter_mut()) {
+        out.flush().unwrap_or_else(|e| fail(&e.to_string()));
+    }
+
+    let (elo, margin) = stats.elo();
+
+    println!();
+    println!("{} vs {}: +{} ={} -{}  score {:.3}  elo {:.1} +/- {:.1}",
+             a.name, b.name, stats.wins, stats.draws, stats.losses,
+             stats.score(), elo, margin);
+
+    if let Some(sprt) = &config.sprt {
+        match stats.sprt(sprt) {
+            SprtResult::AcceptH0 => println!("sprt: H0 accepted"),
+            SprtResult::AcceptH1 => println!("sprt: H1 accepted"),
+            SprtResult::Continue => println!("sprt: no decision"),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use martian_engine::*;
+
+use std::env;
+use std::process;
+use std::time::Instant;
+
+const USAGE: &str = "\
+usage: tablebase PIECES OUTPUT
+
+Solves every position with up to PIECES pieces on the board and writes the
+tables to OUTPUT, to be passed to the engine with --tablebase. PIECES can be
+at most 4, which takes about 30 seconds and 800 MB of memory in a release
+build, making a 6 MB file.";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    if args.len() != 2 {
+        fail(USAGE);
+    }
+
+    let pieces = args[0].parse::<usize>().unwrap_or_else(|_| fail(USAGE));
+
+    if pieces > MAX_TABLEBASE_PIECES {
+        fail(USAGE);
+    }
+
+    let start = Instant::now();
+    let tablebase = Tablebase::generate(pieces);
+
+    println!("solved {} pieces in {:.1}s", pieces, start.elapsed().as_secs_f64());
+
+    tablebase.save(&args[1])
+        .unwrap_or_else(|e| fail(&format!("{}: {}", args[1], e)));
+
+    println!("wrote {}", args[1]);
+}
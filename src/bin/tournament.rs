@@ -0,0 +1,134 @@
+use martian_engine::*;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "\
+usage: tournament --engine [NAME=]COMMAND... [options]
+
+Plays a round robin between engine programs speaking the protocol of the
+martian_engine binary, and prints a crosstable.
+
+options:
+    --engine SPEC      an engine to enter, such as 'new=./martian_engine uci'
+    --tc BASE[+INC]    time per game and increment per move, in
+                       milliseconds (default 10000+100)
+    --games N          games per pair of engines (default 2)
+    --max-moves N      moves before a game is decided on points (default 200)
+    --openings FILE    starting positions, one per line (default: the start)
+    --record FILE      write every game to FILE";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn main() {
+    let mut specs = Vec::new();
+    let mut config = TournamentConfig {
+        time_control: TimeControl {base: 10000, increment: 100},
+        games_per_pair: 2,
+        max_moves: 200,
+    };
+    let mut openings_path = None;
+    let mut record = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+
+        match arg.as_str() {
+            "--engine" => {
+                specs.push(EngineSpec::parse(&value()).unwrap_or_else(|| fail(USAGE)))
+            }
+            "--tc" => {
+                config.time_control =
+                    TimeControl::parse(&value()).unwrap_or_else(|| fail(USAGE))
+            }
+            "--games" => {
+                config.games_per_pair = value().parse().unwrap_or_else(|_| fail(USAGE))
+            }
+            "--max-moves" => {
+                config.max_moves = value().parse().unwrap_or_else(|_| fail(USAGE))
+            }
+            "--openings" => openings_path = Some(value()),
+            "--record" => {
+                let path = value();
+                let file = File::create(&path)
+                    .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+
+                record = Some(BufWriter::new(file));
+            }
+            _ => fail(USAGE)
+        }
+    }
+
+    if specs.len() < 2 {
+        fail(USAGE);
+    }
+
+    let tables = &TABLES;
+
+    let openings = match openings_path {
+        Some(path) => {
+            let text = fs::read_to_string(&path)
+                .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+
+            let mut out = Vec::new();
+
+            for (i, line) in text.lines().enumerate() {
+                let line = line.trim();
+
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                match Position::parse_fen(line, tables) {
+                    Some(pos) => out.push(pos),
+                    None => eprintln!("{}:{}: skipping invalid line", path, i + 1),
+                }
+            }
+
+            out
+        }
+        None => vec![Position::new(tables)],
+    };
+
+    let mut engines = specs
+        .iter()
+        .map(|spec| {
+            EngineProcess::start(spec)
+                .unwrap_or_else(|e| fail(&format!("{}: {}", spec.name, e)))
+        })
+        .collect::<Vec<_>>();
+
+    let mut count = 0;
+
+    let table = run_tournament(&mut engines, &openings, &config, |g, _| {
+        let names = (&specs[g.p1].name, &specs[g.p2].name);
+        let [p1, p2] = g.game.points();
+
+        count += 1;
+
+        println!("game {:4}  {} vs {}  {} ({}-{})  {}",
+                 count, names.0, names.1, result_string(g.result), p1, p2,
+                 g.termination);
+
+        if let Some(out) = &mut record {
+            writeln!(out, "# game {}: {} vs {}, {}, {}",
+                     count, names.0, names.1, result_string(g.result), g.termination)
+                .and_then(|_| writeln!(out, "{}", g.game.to_record()))
+                .unwrap_or_else(|e| fail(&e.to_string()));
+        }
+    });
+
+    if let Some(out) = &mut record {
+        out.flush().unwrap_or_else(|e| fail(&e.to_string()));
+    }
+
+    println!();
+    print!("{}", table);
+}
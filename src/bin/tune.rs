@@ -0,0 +1,90 @@
+use martian_engine::*;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+
+const USAGE: &str = "\
+usage: tune POSITIONS OUTPUT [options]
+
+POSITIONS holds one position per line, written as by Position::to_fen and
+followed by the result for player 1 (1-0, 0-1, 1/2-1/2 or a number from 0 to
+1). The tuned weights are written to OUTPUT in the parameter file format.
+
+options:
+    --params FILE      start from the weights in FILE
+    --iterations N     optimizer steps (default 1000)
+    --rate R           learning rate (default 1.0)";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn main() {
+    let mut paths = Vec::new();
+    let mut params = EvalParams::default();
+    let mut iterations = 1000;
+    let mut rate = 1.0;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+
+        match arg.as_str() {
+            "--params" => {
+                let path = value();
+
+                params = EvalParams::load(&path)
+                    .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            }
+            "--iterations" => {
+                iterations = value().parse().unwrap_or_else(|_| fail(USAGE));
+            }
+            "--rate" => {
+                rate = value().parse().unwrap_or_else(|_| fail(USAGE));
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        fail(USAGE);
+    }
+
+    let tables = &TABLES;
+    let file = File::open(&paths[0])
+        .unwrap_or_else(|e| fail(&format!("{}: {}", paths[0], e)));
+
+    let mut positions = Vec::new();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.unwrap_or_else(|e| fail(&format!("{}: {}", paths[0], e)));
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match parse_sample_line(&line, tables) {
+            Some(p) => positions.push(p),
+            None => eprintln!("{}:{}: skipping invalid line", paths[0], i + 1),
+        }
+    }
+
+    println!("loaded {} positions", positions.len());
+
+    let mut tuner = Tuner::new(make_samples(&positions));
+
+    println!("k = {:.4}", tuner.fit_k(&params));
+
+    let tuned = tuner.tune(&params, iterations, rate, 50, |i, error| {
+        println!("iteration {:5}  error {:.6}", i, error);
+    });
+
+    tuned.save(&paths[1])
+        .unwrap_or_else(|e| fail(&format!("{}: {}", paths[1], e)));
+
+    println!("wrote {}", paths[1]);
+}
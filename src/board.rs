@@ -1,4 +1,7 @@
 use crate::gen_tables::*;
+use crate::rules::*;
+use crate::render::*;
+#[cfg(feature = "simd")]
 use packed_simd::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -20,6 +23,11 @@ const PLAYER: u64 = 0x0000ffff0000ffff;
 
 const START: &str = "qqd1/qdp1/dpp1/4/4/1ppd/1pdq/1dqq";
 
+/// The squares of the half of the board containing `sq`.
+pub fn half_mask(sq: usize) -> u32 {
+    if sq < 16 {0x0000ffff} else {0xffff0000}
+}
+
 #[allow(dead_code)]
 impl Board {
     pub fn from_desc(desc: &str) -> Self {
@@ -45,10 +53,76 @@ impl Board {
         Self(out)
     }
 
+    /// Like `from_desc`, but `None` unless `desc` has 8 ranks of 4 squares
+    /// each, written with only pieces and counts of empty squares.
+    pub fn parse_desc(desc: &str) -> Option<Self> {
+        let ranks = desc.split('/').collect::<Vec<_>>();
+
+        if ranks.len() != 8 {
+            return None;
+        }
+
+        for rank in ranks {
+            let mut squares = 0;
+
+            for c in rank.chars() {
+                squares += match c {
+                    'q' | 'd' | 'p' => 1,
+                    '1'..='4' => c as usize - '0' as usize,
+                    _ => return None,
+                };
+            }
+
+            if squares != 4 {
+                return None;
+            }
+        }
+
+        Some(Self::from_desc(desc))
+    }
+
+    /// The inverse of `from_desc`, describing each rank from 8 to 1 and each
+    /// file from left to right as printed by `Display`.
+    pub fn to_desc(&self) -> String {
+        let mut out = String::new();
+
+        for y in (0..8).rev() {
+            let mut empty = 0;
+
+            for x in (0..4).rev() {
+                let sq = x + y * 4;
+                let c = match (self.0 >> sq & 1, self.0 >> (sq + 32) & 1) {
+                    (1, 1) => 'q',
+                    (0, 1) => 'd',
+                    (1, 0) => 'p',
+                    _ => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+
+                if empty > 0 {
+                    out += &empty.to_string();
+                    empty = 0;
+                }
+                out.push(c);
+            }
+
+            if empty > 0 {
+                out += &empty.to_string();
+            }
+            if y > 0 {
+                out.push('/');
+            }
+        }
+
+        out
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub fn to_js(&self) -> JsBoard {
         unsafe {
-            let out = std::mem::transmute::<_, [i32; 2]>(self.0);
+            let out = std::mem::transmute::<u64, [i32; 2]>(self.0);
 
             JsBoard {
                 upper: out[1],
@@ -60,7 +134,7 @@ impl Board {
     #[cfg(target_arch = "wasm32")]
     pub fn from_js(jsboard: JsBoard) -> Self {
         unsafe {
-            Board(std::mem::transmute::<_, u64>([jsboard.lower, jsboard.upper]))
+            Board(std::mem::transmute::<[i32; 2], u64>([jsboard.lower, jsboard.upper]))
         }
     }
 
@@ -72,22 +146,78 @@ impl Board {
         Board(0)
     }
 
-    fn occ(&self) -> u32 {
+    pub fn occ(&self) -> u32 {
         (self.0 | (self.0 >> 32)) as u32
     }
 
-    fn pawns(&self) -> u32 {
+    pub fn pawns(&self) -> u32 {
         (self.0 & !(self.0 >> 32)) as u32
     }
 
-    fn drones(&self) -> u32 {
+    pub fn drones(&self) -> u32 {
         (!self.0 & (self.0 >> 32)) as u32
     }
 
-    fn queens(&self) -> u32 {
+    pub fn queens(&self) -> u32 {
         (self.0 & (self.0 >> 32)) as u32
     }
 
+    /// The board turned 180 degrees, so that each player's half takes the
+    /// place of the other's.
+    pub fn rotate(&self) -> Self {
+        let low  = (self.0 as u32).reverse_bits() as u64;
+        let high = ((self.0 >> 32) as u32).reverse_bits() as u64;
+
+        Board(low | high << 32)
+    }
+
+    /// The material on the board with the given values of pawns, drones and
+    /// queens.
+    pub fn material(&self, values: &[i32; 3]) -> i32 {
+        self.pawns().count_ones() as i32 * values[0] +
+            self.drones().count_ones() as i32 * values[1] +
+            self.queens().count_ones() as i32 * values[2]
+    }
+
+    /// The points `player` scores by moving from this board to `next`. A
+    /// move that leaves a new piece on the mover's side is a plain move or
+    /// a field promotion and scores nothing.
+    pub fn captured_value(&self, next: &Board, player: bool, values: &[i32; 3])
+        -> i32
+    {
+        let own = if player {!PLAYER} else {PLAYER};
+
+        if next.0 & !self.0 & own != 0 {
+            return 0;
+        }
+
+        (self.material(values) - next.material(values)).max(0)
+    }
+
+    /// The material on the board: one point per pawn, two per drone and
+    /// three per queen.
+    #[cfg(not(feature = "simd"))]
+    pub fn pieces_value(&self) -> i32 {
+        self.pieces_value_scalar()
+    }
+
+    /// The material of player 2 minus that of player 1.
+    #[cfg(not(feature = "simd"))]
+    pub fn player_value(&self) -> i32 {
+        self.player_value_scalar()
+    }
+
+    fn pieces_value_scalar(&self) -> i32 {
+        ((self.0 as u32).count_ones() + 2 * ((self.0 >> 32) as u32).count_ones()) as i32
+    }
+
+    fn player_value_scalar(&self) -> i32 {
+        let count = |shift: u32| (self.0 >> shift & 0xffff).count_ones() as i32;
+
+        -count(0) + count(16) - 2 * count(32) + 2 * count(48)
+    }
+
+    #[cfg(feature = "simd")]
     pub fn pieces_value(&self) -> i32 {
         let block = (1 << 32) - 1;
         let block2 = block << 32;
@@ -103,6 +233,7 @@ impl Board {
         vec.wrapping_sum() as i32
     }
 
+    #[cfg(feature = "simd")]
     pub fn player_value(&self) -> i32 {
         let block = (1 << 16) - 1;
         let mut vec = u64x4::splat(self.0);
@@ -116,46 +247,13 @@ impl Board {
         vec.wrapping_sum()
     }
 
-    fn gen_drone_moves(tables: &Tables, sq: usize, occ: u32) -> u32 {
-        let (mask, magic, shift, table) = &tables.drone[sq];
-        let mut o = occ;
-
-        o &= *mask;
-        o = o.overflowing_mul(*magic).0;
-        o >>= *shift;
-
-        table[o as usize]
-    }
-
-    fn gen_field_drone_moves(tables: &Tables, sq: usize, occ: u32) -> u32 {
-        let (mask, magic, shift, table) = &tables.field_drone[sq];
-        let mut o = occ;
-
-        o &= *mask;
-        o = o.overflowing_mul(*magic).0;
-        o >>= *shift;
-
-        table[o as usize]
-    }
-
-    fn gen_queen_moves(tables: &Tables, sq: usize, occ: u32) -> u32 {
-        let (mask, magic, shift, table) = &tables.queen1[sq];
-        let mut o = occ;
-
-        o &= *mask;
-        o = o.overflowing_mul(*magic).0;
-        o >>= *shift;
-
-        let moves = table[o as usize];
-
-        let (mask, magic, shift, table) = &tables.queen2[sq];
-        o = occ;
-
-        o &= *mask;
-        o = o.overflowing_mul(*magic).0;
-        o >>= *shift;
-
-        moves | table[o as usize]
+    /// Squares a piece of type `piece` (0 pawn, 1 drone, 2 queen) standing on
+    /// `sq` could move to with occupancy `occ`, including captures but not
+    /// field promotions or the no-reversal rule.
+    pub fn piece_attacks(tables: &Tables, piece: usize, sq: usize, occ: u32)
+        -> u32
+    {
+        tables.attacks(piece.min(2), sq, occ)
     }
 
     fn do_moves(&self, sq: usize, moves: u32, out: &mut Vec<Board>) {
@@ -193,7 +291,18 @@ impl Board {
                      player: bool,
                      prev: Board,
                      tables: &Tables,
-                     mut out: &mut Vec<Board>)
+                     out: &mut Vec<Board>)
+    {
+        self.gen_moves_with(player, prev, tables, &RuleSet::STANDARD, out)
+    }
+
+    /// `gen_moves` under `rules` rather than the standard rules.
+    pub fn gen_moves_with(&self,
+                          player: bool,
+                          prev: Board,
+                          tables: &Tables,
+                          rules: &RuleSet,
+                          out: &mut Vec<Board>)
     {
         out.clear();
 
@@ -222,34 +331,43 @@ impl Board {
 
         pawn_field_occ &= player as u32;
 
+        if !rules.field_promotion {
+            pawn_field_occ = 0;
+        }
+
         for sq in LocStack(player_board.pawns()) {
-            let moves = tables.pawn[sq];
+            let moves = tables.attacks(0, sq, occ);
+            let field_moves = tables.field_attacks(0, sq, occ);
 
-            self.do_moves(sq, moves & !player_occ, &mut out);
-            self.do_field_moves(sq, moves & pawn_field_occ, &mut out);
+            self.do_moves(sq, moves & !player_occ, out);
+            self.do_field_moves(sq, field_moves & pawn_field_occ, out);
         }
 
-        if has_queens {
+        if has_queens || !rules.field_promotion {
             for sq in LocStack(player_board.drones()) {
-                let moves = Self::gen_drone_moves(&tables, sq, occ);
-                self.do_moves(sq, moves, &mut out);
+                let moves = tables.attacks(1, sq, occ);
+                self.do_moves(sq, moves, out);
             }
         } else {
             for sq in LocStack(player_board.drones()) {
-                let mut moves = Self::gen_field_drone_moves(&tables, sq, occ);
+                let mut moves = tables.field_attacks(1, sq, occ);
                 let mut field_moves = moves;
 
                 moves &= !player_occ;
                 field_moves &= pawns & player_occ;
 
-                self.do_moves(sq, moves, &mut out);
-                self.do_field_moves(sq, field_moves, &mut out);
+                self.do_moves(sq, moves, out);
+                self.do_field_moves(sq, field_moves, out);
             }
         }
 
         for sq in LocStack(player_board.queens()) {
-            let moves = Self::gen_queen_moves(&tables, sq, occ);
-            self.do_moves(sq, moves, &mut out);
+            let moves = tables.attacks(2, sq, occ);
+            self.do_moves(sq, moves, out);
+        }
+
+        if !rules.no_reversal {
+            return;
         }
 
         let opp = !player;
@@ -260,7 +378,7 @@ impl Board {
     pub fn gen_takes(&self,
                      player: bool,
                      tables: &Tables,
-                     mut out: &mut Vec<Board>)
+                     out: &mut Vec<Board>)
     {
         out.clear();
 
@@ -272,33 +390,36 @@ impl Board {
         let occ = self.occ();
 
         for sq in LocStack(player_board.pawns()) {
-            let moves = tables.pawn[sq] & other_occ;
+            let moves = tables.attacks(0, sq, occ) & other_occ;
 
-            self.do_moves(sq, moves, &mut out);
+            self.do_moves(sq, moves, out);
         }
 
         for sq in LocStack(player_board.drones()) {
-            let moves = Self::gen_drone_moves(&tables, sq, occ) & other_occ;
-            self.do_moves(sq, moves, &mut out);
+            let moves = tables.attacks(1, sq, occ) & other_occ;
+            self.do_moves(sq, moves, out);
         }
 
         for sq in LocStack(player_board.queens()) {
-            let moves = Self::gen_queen_moves(&tables, sq, occ) & other_occ;
-            self.do_moves(sq, moves, &mut out);
+            let moves = tables.attacks(2, sq, occ) & other_occ;
+            self.do_moves(sq, moves, out);
         }
     }
 
-    pub fn gen_piece_moves(&self, prev: Board, tables: &Tables, sq: usize)
+    pub fn gen_piece_moves(&self,
+                           prev: Board,
+                           tables: &Tables,
+                           rules: &RuleSet,
+                           sq: usize)
         -> u32
     {
-        let player = if sq >= 16 {!PLAYER} else {PLAYER};
         let mut moves = Vec::new();
 
         let pawns  = self.pawns();
         let drones = self.drones();
         let queens = self.queens();
 
-        self.gen_moves(sq >= 16, prev, tables, &mut moves);
+        self.gen_moves_with(sq >= 16, prev, tables, rules, &mut moves);
 
         let mut out = 0;
 
@@ -311,16 +432,15 @@ impl Board {
                 out |= diff;
 
                 if queens & 1 << sq != 0 {
-                    let moves = Self::gen_queen_moves(tables, sq, self.occ());
+                    let moves = tables.attacks(2, sq, self.occ());
 
                     out |= moves & queens;
                 } else if drones & 1 << sq != 0 {
-                    let moves = Self::gen_drone_moves(tables, sq, self.occ());
+                    let moves = tables.attacks(1, sq, self.occ());
 
                     out |= moves & drones;
                 } else if pawns & 1 << sq != 0 {
-                    let moves = tables.pawn[sq] &
-                        !(self.occ() & player as u32);
+                    let moves = tables.attacks(0, sq, self.occ());
 
                     out |= moves & pawns;
                 } else {
@@ -343,20 +463,19 @@ impl Board {
         if let Some(l) = locs.next() {
             loc2 = l;
         } else if self.queens() & 1 << loc1 != 0 {
-            let moves = Self::gen_queen_moves(&tables, loc1, self.occ());
+            let moves = tables.attacks(2, loc1, self.occ());
 
             if let Some(l2) = LocStack(moves & self.queens()).next() {
                 loc2 = l2;
             }
         } else if self.drones() & 1 << loc1 != 0 {
-            let moves = Self::gen_drone_moves(&tables, loc1, self.occ());
+            let moves = tables.attacks(1, loc1, self.occ());
 
             if let Some(l2) = LocStack(moves & self.drones()).next() {
                 loc2 = l2;
             }
         } else if self.pawns() & 1 << loc1 != 0 {
-            let player = if loc1 >= 16 {!PLAYER} else {PLAYER};
-            let moves = tables.pawn[loc1] & !(self.occ() & player as u32);
+            let moves = tables.attacks(0, loc1, self.occ());
 
             if let Some(l2) = LocStack(moves & self.pawns()).next() {
                 loc2 = l2;
@@ -420,44 +539,7 @@ use std::fmt;
 
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let player = false;
-
-        let y_iter: Box<dyn Iterator<Item = usize>> =
-            if player {Box::new(0..8)} else {Box::new((0..8).rev())};
-
-        for y in y_iter {
-            write!(f, "{}  ", y + 1)?;
-
-            let x_iter: Box<dyn Iterator<Item = usize>> =
-                if player {Box::new(0..4)} else {Box::new((0..4).rev())};
-
-            for x in x_iter {
-                let sq = x + y * 4;
-                let mut tmp = 0;
-
-                tmp |= (self.0 & (1 << sq)) >> sq;
-                tmp |= (self.0 & (1 << (sq + 32))) >> (sq + 31);
-
-                write!(f, "{} ", 
-                    match tmp {
-                        1 => '^',
-                        2 => '*',
-                        3 => 'A',
-                        _ => '_'
-                    }
-                )?;
-            }
-            writeln!(f)?;
-        }
-        writeln!(f)?;
-
-        if player {
-            writeln!(f, "   D C B A")?;
-        } else {
-            writeln!(f, "   A B C D")?;
-        }
-
-        Ok(())
+        write!(f, "{}", render(self, &RenderOptions::default()))
     }
 }
 
@@ -469,68 +551,124 @@ impl Default for Board {
 
 #[allow(unused_imports)]
 mod tests {
-    extern crate test;
-    use test::Bencher;
+    use crate::magic::*;
     use super::*;
 
-    #[bench]
-    fn b_pieces_value(b: &mut Bencher) {
-        let board = Board::new();
-
-        b.iter(|| test::black_box(&board).pieces_value());
-    }
-
-    #[bench]
-    fn b_player_value(b: &mut Bencher) {
-        let board = Board::new();
-
-        b.iter(|| test::black_box(&board).player_value());
-    }
+    #[test]
+    fn t_material_values() {
+        // a slow count square by square to check both implementations against
+        let expected = |board: Board| {
+            (0..32).fold((0, 0), |(pieces, player), sq| {
+                let value = (board.0 >> sq & 1) + 2 * (board.0 >> (sq + 32) & 1);
+                let sign = if sq < 16 {-1} else {1};
+
+                (pieces + value as i32, player + sign * value as i32)
+            })
+        };
+
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        let mut boards = vec![Board::new(), Board::empty(), Board(!0)];
+
+        for _ in 0..1000 {
+            boards.push(Board(rng.next_u64()));
+        }
 
-    #[bench]
-    fn b_gen_moves(b: &mut Bencher) {
-        let mut moves = Vec::new();
-        let board = Board(0xea9020804b100000);
-        let tables = Tables::new();
+        for &board in boards.iter() {
+            let (pieces, player) = expected(board);
 
-        b.iter(|| {test::black_box(&board).gen_moves(false, Board(0), &tables, &mut moves); moves.clone()});
+            assert_eq!(board.pieces_value_scalar(), pieces);
+            assert_eq!(board.player_value_scalar(), player);
+            assert_eq!(board.pieces_value(), pieces);
+            assert_eq!(board.player_value(), player);
+        }
     }
 
-    #[bench]
-    fn b_gen_takes(b: &mut Bencher) {
+    #[test]
+    fn t_captured_value() {
+        let tables = &TABLES;
+        let values = [1, 5, 9];
         let mut moves = Vec::new();
-        let board = Board(0xea9020804b100000);
-        let tables = Tables::new();
 
-        b.iter(|| {test::black_box(&board).gen_takes(false, &tables, &mut moves); moves.clone()});
+        let boards = [
+            Board::new(),
+            Board::from_desc("4/4/4/1p1p/2p1/1p1p/4/4"),
+            Board::from_desc("4/4/4/2p1/1pdp/2p1/2p1/4"),
+            Board::from_desc("qdp1/4/1d2/3q/pp2/1q2/4/3d"),
+        ];
+
+        for &board in boards.iter() {
+            for &player in [false, true].iter() {
+                let other = if player {PLAYER} else {!PLAYER} as u32;
+                let half = |b: &Board, mask: u32| {
+                    Board(b.0 & (mask as u64 * SQUARE)).material(&values)
+                };
+
+                board.gen_moves(player, Board(0), tables, &mut moves);
+
+                for mov in moves.iter() {
+                    // a capture takes material off the mover's side, which
+                    // a field promotion doesn't, and lands on an enemy piece
+                    // rather than an empty square
+                    let from = board.occ() & !mov.occ() & !other;
+                    let own = |b: &Board| {
+                        Board(b.0 & (!other as u64 * SQUARE)).pieces_value()
+                    };
+                    let crossed = own(mov) < own(&board) &&
+                        (mov.occ() & other) == (board.occ() & other);
+                    let expected = if crossed {
+                        half(&board, other) - half(mov, other) + half(&board, from)
+                    } else {
+                        0
+                    };
+
+                    assert_eq!(board.captured_value(mov, player, &values), expected);
+                }
+            }
+        }
     }
 
     #[test]
     fn t_field_promotions() {
         let board = Board::from_desc("4/4/4/1p1p/2p1/1p1p/4/4");
-        let tables = Tables::new();
+        let tables = &TABLES;
         let mut moves = Vec::new();
 
-        board.gen_moves(false, Board(0), &tables, &mut moves);
+        board.gen_moves(false, Board(0), tables, &mut moves);
 
         assert_eq!(moves.len(), 10);
 
         let board2 = Board::from_desc("4/4/4/2p1/1pdp/2p1/2p1/4");
 
-        board2.gen_moves(false, Board(0), &tables, &mut moves);
+        board2.gen_moves(false, Board(0), tables, &mut moves);
 
         assert_eq!(moves.len(), 14);
 
         let board3 = Board::from_desc("q3/4/4/4/4/4/4/3d");
 
-        board3.gen_moves(false, Board(0), &tables, &mut moves);
+        board3.gen_moves(false, Board(0), tables, &mut moves);
 
         assert_eq!(moves.len(), 4);
 
         let board4 = Board::from_desc("q3/4/4/4/4/4/4/3d");
 
-        board4.gen_moves(true, Board(0), &tables, &mut moves);
+        board4.gen_moves(true, Board(0), tables, &mut moves);
 
         assert_eq!(moves.len(), 13);
     }
+
+    #[test]
+    fn t_desc() {
+        assert_eq!(Board::new().to_desc(), START);
+        assert_eq!(Board::empty().to_desc(), "4/4/4/4/4/4/4/4");
+
+        for desc in ["q3/4/4/1d2/2p1/4/4/3q", "4/4/4/2p1/1pdp/2p1/2p1/4"].iter() {
+            assert_eq!(Board::from_desc(desc).to_desc(), *desc);
+            assert_eq!(Board::parse_desc(desc), Some(Board::from_desc(desc)));
+        }
+
+        for desc in ["4/4/4/4/4/4/4/ppppppp", "x/y", "ppppp/4/4/4/4/4/4/4",
+                     "4/4/4/4/4/4/4/3", "4/4/4/4/4/4/4/4/4", "4/4/4/4/4/4/4/3x"].iter() {
+            assert_eq!(Board::parse_desc(desc), None);
+        }
+    }
 }
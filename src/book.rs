@@ -0,0 +1,270 @@
+use crate::board::*;
+use crate::position::*;
+use crate::game::*;
+
+use rand::Rng;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"MOB1";
+
+/// A move of a book position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookMove {
+    /// the board after the move
+    pub board: Board,
+    /// how likely the move is to be played: two for each game it won and
+    /// one for each it drew
+    pub weight: u32,
+    /// the results of the games it was played in, for the player that
+    /// played it
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl BookMove {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+}
+
+/// An opening book: the moves played from the early positions of past
+/// games, by the `Position::key` of the position they are played from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Book {
+    entries: BTreeMap<u64, Vec<BookMove>>,
+}
+
+impl Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The positions' keys and their moves, in order of key.
+    pub fn entries(&self) -> impl Iterator<Item = (u64, &[BookMove])> {
+        self.entries.iter().map(|(&key, moves)| (key, moves.as_slice()))
+    }
+
+    /// The moves stored for `pos`, legal or not.
+    pub fn moves(&self, pos: &Position) -> &[BookMove] {
+        self.entries.get(&pos.key()).map_or(&[], |moves| moves.as_slice())
+    }
+
+    /// Adds the first `max_plies` moves of `game`, scored by how the game
+    /// went on points.
+    pub fn add_game(&mut self, game: &Game, max_plies: usize) {
+        let outcome = game.outcome();
+
+        for (pos, &board) in game.positions().iter().zip(game.moves()).take(max_plies) {
+            let result = if pos.get_player() {1.0 - outcome} else {outcome};
+            let moves = self.entries.entry(pos.key()).or_default();
+            let index = match moves.iter().position(|m| m.board == board) {
+                Some(index) => index,
+                None => {
+                    moves.push(BookMove {board, weight: 0, wins: 0, draws: 0, losses: 0});
+                    moves.len() - 1
+                }
+            };
+            let m = &mut moves[index];
+
+            if result > 0.5 {
+                m.wins += 1;
+                m.weight += 2;
+            } else if result < 0.5 {
+                m.losses += 1;
+            } else {
+                m.draws += 1;
+                m.weight += 1;
+            }
+        }
+    }
+
+    /// A legal book move of `pos` picked at random in proportion to the
+    /// weights, or `None` once the book has run out.
+    pub fn probe(&self, pos: &Position) -> Option<Board> {
+        let mut legal = Vec::new();
+
+        pos.gen_moves(&mut legal);
+
+        let moves = self.moves(pos)
+            .iter()
+            .filter(|m| m.weight > 0 && legal.contains(&m.board))
+            .collect::<Vec<_>>();
+        let total = moves.iter().map(|m| m.weight as u64).sum::<u64>();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = rand::thread_rng().gen_range(0..total);
+
+        for m in moves {
+            if pick < m.weight as u64 {
+                return Some(m.board);
+            }
+            pick -= m.weight as u64;
+        }
+
+        unreachable!()
+    }
+
+    /// The book in the file format: `MOB1` and the number of positions,
+    /// then for each position its key and number of moves followed by the
+    /// moves' boards, weights, wins, draws and losses, all little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+
+        out.extend((self.entries.len() as u32).to_le_bytes());
+
+        for (key, moves) in self.entries.iter() {
+            out.extend(key.to_le_bytes());
+            out.extend((moves.len() as u16).to_le_bytes());
+
+            for m in moves {
+                out.extend(m.board.0.to_le_bytes());
+
+                for n in [m.weight, m.wins, m.draws, m.losses] {
+                    out.extend(n.to_le_bytes());
+                }
+            }
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(invalid_data("not an opening book file"));
+        }
+
+        let mut rest = &bytes[4..];
+
+        let count = u32::from_le_bytes(read(&mut rest)?);
+        let mut entries = BTreeMap::new();
+
+        for _ in 0..count {
+            let key = u64::from_le_bytes(read(&mut rest)?);
+            let len = u16::from_le_bytes(read(&mut rest)?);
+            let mut moves = Vec::with_capacity(len as usize);
+
+            for _ in 0..len {
+                let board = Board(u64::from_le_bytes(read(&mut rest)?));
+
+                moves.push(BookMove {
+                    board,
+                    weight: u32::from_le_bytes(read(&mut rest)?),
+                    wins: u32::from_le_bytes(read(&mut rest)?),
+                    draws: u32::from_le_bytes(read(&mut rest)?),
+                    losses: u32::from_le_bytes(read(&mut rest)?),
+                });
+            }
+
+            entries.insert(key, moves);
+        }
+
+        if !rest.is_empty() {
+            return Err(invalid_data("opening book file has trailing bytes"));
+        }
+
+        Ok(Self {entries})
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// The next `N` bytes of `rest`, moving past them.
+fn read<const N: usize>(rest: &mut &[u8]) -> io::Result<[u8; N]> {
+    if rest.len() < N {
+        return Err(invalid_data("opening book file is truncated"));
+    }
+
+    let mut out = [0; N];
+
+    out.copy_from_slice(&rest[..N]);
+    *rest = &rest[N..];
+    Ok(out)
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::gen_tables::*;
+    use super::*;
+
+    #[test]
+    fn t_book() {
+        let tables = &TABLES;
+        let desc = "4/4/4/1q2/2p1/4/4/3p";
+        let mut book = Book::new();
+
+        // taking the queen wins on points, the other move draws or loses
+        for moves in ["c4b5", "c4b5", "d1c2", "d1c2 b5c4"] {
+            let mut game = Game::parse(desc, tables).unwrap();
+
+            for m in moves.split_whitespace() {
+                assert!(game.do_string_move(m));
+            }
+
+            book.add_game(&game, 2);
+        }
+
+        let start = Game::parse(desc, tables).unwrap().position().clone();
+        let stats = |pos: &Position, s: &str| {
+            let board = pos.board.do_string_move(s);
+            let m = book.moves(pos).iter().find(|m| m.board == board).unwrap();
+
+            (m.weight, m.wins, m.draws, m.losses)
+        };
+
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.moves(&start).len(), 2);
+        assert_eq!(stats(&start, "c4b5"), (4, 2, 0, 0));
+        assert_eq!(stats(&start, "d1c2"), (1, 0, 1, 1));
+
+        let mut pos = start.clone();
+
+        pos.do_move(pos.board.do_string_move("d1c2"));
+
+        assert_eq!(stats(&pos, "b5c4"), (2, 1, 0, 0));
+        assert_eq!(book.probe(&pos), Some(pos.board.do_string_move("b5c4")));
+
+        // no move once the book runs out
+        pos.do_move(pos.board.do_string_move("b5c4"));
+
+        assert_eq!(book.probe(&pos), None);
+
+        for _ in 0..20 {
+            assert!(book.probe(&start).is_some());
+        }
+
+        let bytes = book.to_bytes();
+
+        assert_eq!(bytes.len(), 8 + 2 * 10 + 3 * 24);
+        assert_eq!(Book::from_bytes(&bytes).unwrap(), book);
+        assert!(Book::from_bytes(&bytes[1..]).is_err());
+        assert!(Book::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Book::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+    }
+}
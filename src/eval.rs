@@ -0,0 +1,718 @@
+use crate::board::*;
+use crate::gen_tables::*;
+use crate::position::*;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Weights used by `EvalParams::eval`. Piece arrays are indexed pawn, drone,
+/// queen, and piece-square tables are indexed by square as seen by the side
+/// to move, so squares 0-15 are always the mover's own half of the board.
+///
+/// The positional terms from `mobility` on are counted for the mover minus
+/// the opponent. They default to zero, leaving the stock evaluation as it
+/// was until weights are tuned or loaded from a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalParams {
+    /// per point of captured score difference
+    pub captured: i32,
+    /// per piece in the mover's half
+    pub own_material: [i32; 3],
+    /// per piece in the opponent's half
+    pub other_material: [i32; 3],
+    pub pst: [[i32; 32]; 3],
+    /// multiplier applied to the evaluation of a finished game
+    pub end_scale: i32,
+    /// per move available to a piece
+    pub mobility: [i32; 3],
+    /// per attacked piece where some capture of it leaves the capturing
+    /// piece, now owned by the defender, unable to capture back
+    pub hanging: [i32; 3],
+    /// per attacked piece where every capture of it can be answered by the
+    /// capturing piece taking back from the square it lands on
+    pub defended: [i32; 3],
+    /// per square next to the canal that a side's pieces can move to
+    pub canal: i32,
+}
+
+pub const DEFAULT_PARAMS: EvalParams = EvalParams {
+    captured: 100,
+    own_material: [-1, -2, -3],
+    other_material: [1, 2, 3],
+    pst: [[0; 32]; 3],
+    end_scale: 100,
+    mobility: [0; 3],
+    hanging: [0; 3],
+    defended: [0; 3],
+    canal: 0,
+};
+
+pub const PIECE_NAMES: [&str; 3] = ["pawn", "drone", "queen"];
+
+// offsets of each group of weights in `EvalParams::to_weights`
+const W_CAPTURED: usize = 0;
+const W_OWN: usize = 1;
+const W_OTHER: usize = 4;
+const W_PST: usize = 7;
+const W_MOBILITY: usize = 103;
+const W_HANGING: usize = 106;
+const W_DEFENDED: usize = 109;
+const W_CANAL: usize = 112;
+
+pub const NUM_WEIGHTS: usize = 113;
+
+const OWN_HALF: u32 = 0x0000ffff;
+const HALVES: [u32; 2] = [OWN_HALF, !OWN_HALF];
+
+/// The two rows on either side of the canal.
+const CANAL: u32 = 0x000ff000;
+
+/// Positional features of a board oriented so that the side to move owns
+/// squares 0-15. Arrays are indexed by side (0 for the mover, 1 for the
+/// opponent), then by piece type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Positional {
+    pub mobility: [[i32; 3]; 2],
+    pub hanging: [[i32; 3]; 2],
+    pub defended: [[i32; 3]; 2],
+    pub canal: [i32; 2],
+}
+
+impl Positional {
+    pub fn new(board: &Board, tables: &Tables) -> Self {
+        let mut out = Self::default();
+
+        let occ = board.occ();
+        let pieces = [board.pawns(), board.drones(), board.queens()];
+
+        // (square, piece, attacked squares) for every piece of each side
+        let mut attackers = [[(0, 0, 0); 16]; 2];
+        let mut counts = [0; 2];
+        let mut controlled = [0; 2];
+
+        for (side, &half) in HALVES.iter().enumerate() {
+            for (piece, &p) in pieces.iter().enumerate() {
+                for sq in LocStack(p & half) {
+                    let att = Board::piece_attacks(tables, piece, sq, occ);
+
+                    out.mobility[side][piece] += att.count_ones() as i32;
+                    controlled[side] |= att;
+
+                    attackers[side][counts[side]] = (sq, piece, att);
+                    counts[side] += 1;
+                }
+            }
+
+            out.canal[side] = (controlled[side] & CANAL).count_ones() as i32;
+        }
+
+        for (side, &half) in HALVES.iter().enumerate() {
+            let enemy = 1 - side;
+            let enemy_attackers = &attackers[enemy][..counts[enemy]];
+
+            for (piece, &p) in pieces.iter().enumerate() {
+                for sq in LocStack(p & half & controlled[enemy]) {
+                    // the capturing piece changes owner on crossing the
+                    // canal, so it can be used to take back immediately
+                    let answered = enemy_attackers
+                        .iter()
+                        .filter(|(_, _, att)| att & 1 << sq != 0)
+                        .all(|&(from, attacker, _)| {
+                            let occ = occ & !(1 << from);
+                            let att =
+                                Board::piece_attacks(tables, attacker, sq, occ);
+
+                            att & occ & HALVES[enemy] != 0
+                        });
+
+                    if answered {
+                        out.defended[side][piece] += 1;
+                    } else {
+                        out.hanging[side][piece] += 1;
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// One component of an evaluation. `value` is the raw feature for the side
+/// to move, counted as mover minus opponent for the positional terms.
+/// Piece-square tables have no single weight, so their `value` is the sum of
+/// the table entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalTerm {
+    pub name: String,
+    pub weight: Option<i32>,
+    pub value: i32,
+    pub contribution: i32,
+}
+
+/// The evaluation of a position split into its terms, as returned by
+/// `EvalParams::explain`. `total` is always equal to `EvalParams::eval`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalExplain {
+    pub terms: Vec<EvalTerm>,
+    pub total: i32,
+}
+
+impl EvalExplain {
+    fn push(&mut self, name: String, weight: Option<i32>, value: i32) {
+        let contribution = weight.map_or(value, |w| w * value);
+
+        self.total += contribution;
+        self.terms.push(EvalTerm {name, weight, value, contribution});
+    }
+}
+
+impl fmt::Display for EvalExplain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16} {:>7} {:>7} {:>7}", "term", "weight", "value", "total")?;
+
+        for term in &self.terms {
+            let weight = term.weight.map_or("-".to_string(), |w| w.to_string());
+
+            writeln!(f, "{:<16} {:>7} {:>7} {:>7}",
+                     term.name, weight, term.value, term.contribution)?;
+        }
+
+        write!(f, "{:<16} {:>7} {:>7} {:>7}", "eval", "", "", self.total)
+    }
+}
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Io(io::Error),
+    Parse {line: usize, msg: String},
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsError::Io(e) => write!(f, "{}", e),
+            ParamsError::Parse {line, msg} => write!(f, "line {}: {}", line, msg),
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+impl From<io::Error> for ParamsError {
+    fn from(e: io::Error) -> Self {
+        ParamsError::Io(e)
+    }
+}
+
+impl EvalParams {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ParamsError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Evaluation of `pos` from the point of view of the side to move.
+    pub fn eval(&self, pos: &Position) -> i32 {
+        let mut board = pos.board;
+        let mut score = pos.get_score();
+
+        if pos.get_player() {
+            board = board.rotate();
+            score = -score;
+        }
+
+        let mut out = self.captured * score;
+
+        let pieces = [board.pawns(), board.drones(), board.queens()];
+
+        for (i, &p) in pieces.iter().enumerate() {
+            out += self.own_material[i]   * (p &  OWN_HALF).count_ones() as i32;
+            out += self.other_material[i] * (p & !OWN_HALF).count_ones() as i32;
+
+            for sq in LocStack(p) {
+                out += self.pst[i][sq];
+            }
+        }
+
+        if self.has_positional() {
+            out += self.positional(&Positional::new(&board, pos.get_tables()));
+        }
+
+        out
+    }
+
+    /// The terms making up `eval(pos)`, including positional terms whose
+    /// weight is zero.
+    pub fn explain(&self, pos: &Position) -> EvalExplain {
+        let mut board = pos.board;
+        let mut score = pos.get_score();
+
+        if pos.get_player() {
+            board = board.rotate();
+            score = -score;
+        }
+
+        let mut out = EvalExplain {terms: Vec::new(), total: 0};
+
+        out.push("captured".to_string(), Some(self.captured), score);
+
+        let pieces = [board.pawns(), board.drones(), board.queens()];
+
+        for (i, &p) in pieces.iter().enumerate() {
+            let own   = (p &  OWN_HALF).count_ones() as i32;
+            let other = (p & !OWN_HALF).count_ones() as i32;
+
+            out.push(format!("own {}", PIECE_NAMES[i]), Some(self.own_material[i]), own);
+            out.push(format!("other {}", PIECE_NAMES[i]), Some(self.other_material[i]), other);
+        }
+
+        for (i, &p) in pieces.iter().enumerate() {
+            let pst = LocStack(p).map(|sq| self.pst[i][sq]).sum();
+
+            out.push(format!("pst {}", PIECE_NAMES[i]), None, pst);
+        }
+
+        let f = Positional::new(&board, pos.get_tables());
+
+        for (i, name) in PIECE_NAMES.iter().enumerate() {
+            out.push(format!("mobility {}", name),
+                     Some(self.mobility[i]), f.mobility[0][i] - f.mobility[1][i]);
+        }
+
+        for (i, name) in PIECE_NAMES.iter().enumerate() {
+            out.push(format!("hanging {}", name),
+                     Some(self.hanging[i]), f.hanging[0][i] - f.hanging[1][i]);
+        }
+
+        for (i, name) in PIECE_NAMES.iter().enumerate() {
+            out.push(format!("defended {}", name),
+                     Some(self.defended[i]), f.defended[0][i] - f.defended[1][i]);
+        }
+
+        out.push("canal".to_string(), Some(self.canal), f.canal[0] - f.canal[1]);
+
+        out
+    }
+
+    /// All weights of the evaluation as one vector, in the order captured,
+    /// own_material, other_material, pst, mobility, hanging, defended,
+    /// canal. `end_scale` only applies to finished games and is left out.
+    pub fn to_weights(&self) -> Vec<i32> {
+        let mut out = vec![self.captured];
+
+        out.extend_from_slice(&self.own_material);
+        out.extend_from_slice(&self.other_material);
+
+        for pst in self.pst.iter() {
+            out.extend_from_slice(pst);
+        }
+
+        out.extend_from_slice(&self.mobility);
+        out.extend_from_slice(&self.hanging);
+        out.extend_from_slice(&self.defended);
+        out.push(self.canal);
+
+        out
+    }
+
+    /// The inverse of `to_weights`, keeping `end_scale` from `self`.
+    pub fn from_weights(&self, weights: &[i32]) -> Self {
+        let mut out = self.clone();
+
+        out.captured = weights[W_CAPTURED];
+        out.own_material.copy_from_slice(&weights[W_OWN..W_OTHER]);
+        out.other_material.copy_from_slice(&weights[W_OTHER..W_PST]);
+
+        for (i, pst) in out.pst.iter_mut().enumerate() {
+            pst.copy_from_slice(&weights[W_PST + i * 32..W_PST + i * 32 + 32]);
+        }
+
+        out.mobility.copy_from_slice(&weights[W_MOBILITY..W_HANGING]);
+        out.hanging.copy_from_slice(&weights[W_HANGING..W_DEFENDED]);
+        out.defended.copy_from_slice(&weights[W_DEFENDED..W_CANAL]);
+        out.canal = weights[W_CANAL];
+
+        out
+    }
+
+    /// The features of `pos` as `(index, value)` pairs, such that `eval` is
+    /// the dot product of `to_weights` with them. Zero features are omitted.
+    pub fn features(pos: &Position) -> Vec<(usize, i32)> {
+        let mut board = pos.board;
+        let mut score = pos.get_score();
+
+        if pos.get_player() {
+            board = board.rotate();
+            score = -score;
+        }
+
+        let mut out = vec![(W_CAPTURED, score)];
+
+        let pieces = [board.pawns(), board.drones(), board.queens()];
+
+        for (i, &p) in pieces.iter().enumerate() {
+            out.push((W_OWN + i, (p & OWN_HALF).count_ones() as i32));
+            out.push((W_OTHER + i, (p & !OWN_HALF).count_ones() as i32));
+
+            for sq in LocStack(p) {
+                out.push((W_PST + i * 32 + sq, 1));
+            }
+        }
+
+        let f = Positional::new(&board, pos.get_tables());
+
+        for i in 0..3 {
+            out.push((W_MOBILITY + i, f.mobility[0][i] - f.mobility[1][i]));
+            out.push((W_HANGING + i, f.hanging[0][i] - f.hanging[1][i]));
+            out.push((W_DEFENDED + i, f.defended[0][i] - f.defended[1][i]));
+        }
+
+        out.push((W_CANAL, f.canal[0] - f.canal[1]));
+        out.retain(|&(_, x)| x != 0);
+
+        out
+    }
+
+    /// Whether any positional weight is set, since computing the positional
+    /// features costs far more than the rest of the evaluation.
+    pub fn has_positional(&self) -> bool {
+        self.canal != 0 ||
+            [self.mobility, self.hanging, self.defended]
+                .iter()
+                .any(|w| w.iter().any(|&x| x != 0))
+    }
+
+    pub fn positional(&self, f: &Positional) -> i32 {
+        let mut out = self.canal * (f.canal[0] - f.canal[1]);
+
+        for i in 0..3 {
+            out += self.mobility[i] * (f.mobility[0][i] - f.mobility[1][i]);
+            out += self.hanging[i]  * (f.hanging[0][i]  - f.hanging[1][i]);
+            out += self.defended[i] * (f.defended[0][i] - f.defended[1][i]);
+        }
+
+        out
+    }
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        DEFAULT_PARAMS
+    }
+}
+
+fn write_row(f: &mut fmt::Formatter<'_>, name: &str, row: &[i32])
+    -> fmt::Result
+{
+    write!(f, "{}", name)?;
+
+    for x in row {
+        write!(f, " {}", x)?;
+    }
+
+    writeln!(f)
+}
+
+impl fmt::Display for EvalParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_row(f, "captured", &[self.captured])?;
+        write_row(f, "end_scale", &[self.end_scale])?;
+        write_row(f, "own_material", &self.own_material)?;
+        write_row(f, "other_material", &self.other_material)?;
+
+        write_row(f, "mobility", &self.mobility)?;
+        write_row(f, "hanging", &self.hanging)?;
+        write_row(f, "defended", &self.defended)?;
+        write_row(f, "canal", &[self.canal])?;
+
+        for (name, pst) in PIECE_NAMES.iter().zip(self.pst.iter()) {
+            write_row(f, &format!("pst_{}", name), pst)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_row(line: usize, words: &[&str], out: &mut [i32])
+    -> Result<(), ParamsError>
+{
+    if words.len() != out.len() {
+        return Err(ParamsError::Parse {
+            line,
+            msg: format!("expected {} values, found {}", out.len(), words.len())
+        });
+    }
+
+    for (o, w) in out.iter_mut().zip(words) {
+        *o = w.parse().map_err(|_| ParamsError::Parse {
+            line,
+            msg: format!("invalid number '{}'", w)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Parses lines of the form `name value...`, as written by `Display`. Blank
+/// lines and lines starting with `#` are ignored, and any parameter that is
+/// not mentioned keeps its default value.
+impl FromStr for EvalParams {
+    type Err = ParamsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut out = Self::default();
+
+        for (i, line) in s.lines().enumerate() {
+            let line_num = i + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let values = &words[1..];
+
+            match words[0] {
+                "captured" =>
+                    parse_row(line_num, values, std::slice::from_mut(&mut out.captured))?,
+                "end_scale" =>
+                    parse_row(line_num, values, std::slice::from_mut(&mut out.end_scale))?,
+                "own_material" =>
+                    parse_row(line_num, values, &mut out.own_material)?,
+                "other_material" =>
+                    parse_row(line_num, values, &mut out.other_material)?,
+                "mobility" => parse_row(line_num, values, &mut out.mobility)?,
+                "hanging"  => parse_row(line_num, values, &mut out.hanging)?,
+                "defended" => parse_row(line_num, values, &mut out.defended)?,
+                "canal" =>
+                    parse_row(line_num, values, std::slice::from_mut(&mut out.canal))?,
+                "pst_pawn"  => parse_row(line_num, values, &mut out.pst[0])?,
+                "pst_drone" => parse_row(line_num, values, &mut out.pst[1])?,
+                "pst_queen" => parse_row(line_num, values, &mut out.pst[2])?,
+                name => {
+                    return Err(ParamsError::Parse {
+                        line: line_num,
+                        msg: format!("unknown parameter '{}'", name)
+                    });
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_default_params() {
+        let tables = &TABLES;
+        let fens = [
+            "ec200a27ca406643 0000000000000000 1 0",
+            "ec200a27ca406643 ec100a27ca406643 2 3",
+            "ea9020804b100000 0000000000000000 1 -4",
+            "0000c00000030000 0000000000000000 2 5",
+        ];
+
+        for fen in fens.iter() {
+            let pos = Position::from_fen(fen, tables);
+            let mut expected = pos.get_score() * 100 + pos.board.player_value();
+
+            if pos.get_player() {
+                expected = -expected;
+            }
+
+            assert_eq!(DEFAULT_PARAMS.eval(&pos), expected);
+        }
+    }
+
+    #[test]
+    fn t_parse_params() {
+        let mut params = EvalParams {
+            captured: 120,
+            other_material: [2, 3, 5],
+            ..EvalParams::default()
+        };
+
+        params.pst[1][5] = -7;
+        params.hanging[2] = -40;
+
+        assert_eq!(params.to_string().parse::<EvalParams>().unwrap(), params);
+
+        let partial = "# comment\n\ncaptured 90\n".parse::<EvalParams>().unwrap();
+
+        assert_eq!(partial.captured, 90);
+        assert_eq!(partial.own_material, DEFAULT_PARAMS.own_material);
+
+        assert!("captured".parse::<EvalParams>().is_err());
+        assert!("own_material 1 2 x".parse::<EvalParams>().is_err());
+        assert!("bishop 3".parse::<EvalParams>().is_err());
+    }
+
+    #[test]
+    fn t_pst_perspective() {
+        let tables = &TABLES;
+        let mut params = EvalParams {
+            own_material: [0; 3],
+            other_material: [0; 3],
+            ..EvalParams::default()
+        };
+
+        params.pst[0][0] = 10;
+
+        // a pawn on square 0 for player 1 is a pawn on square 31 for player 2
+        let p1 = Position::from_fen("0000000000000001 0000000000000000 1 0", tables);
+        let p2 = Position::from_fen("0000000080000000 0000000000000000 2 0", tables);
+
+        assert_eq!(params.eval(&p1), 10);
+        assert_eq!(params.eval(&p2), 10);
+    }
+
+    #[allow(dead_code)]
+    fn positional_params() -> EvalParams {
+        EvalParams {
+            captured: 0,
+            own_material: [0; 3],
+            other_material: [0; 3],
+            ..EvalParams::default()
+        }
+    }
+
+    #[test]
+    fn t_mobility_and_canal() {
+        let tables = &TABLES;
+
+        // a lone queen in the corner of player 1's half
+        let board = Board::from_desc("4/4/4/4/4/4/4/q3");
+        let pos = Position::from_fen(&format!("{:016x} 0 1 0", board.0), tables);
+        let f = Positional::new(&board, tables);
+
+        assert_eq!(f.mobility[0], [0, 0, 13]);
+        assert_eq!(f.mobility[1], [0, 0, 0]);
+        assert_eq!(f.canal, [3, 0]);
+
+        let params = EvalParams {mobility: [0, 0, 2], ..positional_params()};
+
+        assert_eq!(params.eval(&pos), 26);
+
+        let params = EvalParams {canal: 5, ..positional_params()};
+
+        assert_eq!(params.eval(&pos), 15);
+
+        // the same position seen by player 2 counts against the mover
+        let pos = Position::from_fen(&format!("{:016x} 0 2 0", board.0), tables);
+
+        assert_eq!(params.eval(&pos), -15);
+    }
+
+    #[test]
+    fn t_hanging_and_defended() {
+        let tables = &TABLES;
+
+        // an enemy drone can take the pawn on c4, and the drone has nothing
+        // to take back from there
+        let board = Board::from_desc("4/4/2d1/4/2p1/4/4/4");
+        let f = Positional::new(&board, tables);
+
+        assert_eq!(f.hanging, [[1, 0, 0], [0, 0, 0]]);
+        assert_eq!(f.defended, [[0, 0, 0], [0, 0, 0]]);
+
+        let pos = Position::from_fen(&format!("{:016x} 0 1 0", board.0), tables);
+        let params = EvalParams {hanging: [-50, 0, 0], ..positional_params()};
+
+        assert_eq!(params.eval(&pos), -50);
+
+        // an enemy queen takes instead, and after crossing it could take the
+        // pawn further up the same file
+        let board = Board::from_desc("4/2p1/q3/4/2p1/4/4/4");
+        let f = Positional::new(&board, tables);
+
+        assert_eq!(f.hanging, [[0, 0, 0], [0, 0, 0]]);
+        assert_eq!(f.defended, [[1, 0, 0], [0, 0, 0]]);
+
+        let pos = Position::from_fen(&format!("{:016x} 0 1 0", board.0), tables);
+        let params = EvalParams {defended: [7, 0, 0], ..positional_params()};
+
+        assert_eq!(params.eval(&pos), 7);
+    }
+
+    #[test]
+    fn t_explain() {
+        let tables = &TABLES;
+        let mut params = EvalParams {
+            captured: 90,
+            own_material: [-2, -3, -4],
+            other_material: [1, 3, 4],
+            mobility: [1, 2, 1],
+            hanging: [-30, -40, -60],
+            defended: [-5, -10, -15],
+            canal: 4,
+            ..EvalParams::default()
+        };
+
+        params.pst[0][13] = 6;
+        params.pst[2][30] = -9;
+
+        let fens = [
+            "ec200a27ca406643 0000000000000000 1 0",
+            "ec200a27ca406643 ec100a27ca406643 2 3",
+            "ea9020804b100000 0000000000000000 1 -4",
+            "0000c00000030000 0000000000000000 2 5",
+        ];
+
+        for fen in fens.iter() {
+            let pos = Position::from_fen(fen, tables);
+            let explain = params.explain(&pos);
+
+            assert_eq!(explain.total, params.eval(&pos));
+            assert_eq!(
+                explain.terms.iter().map(|t| t.contribution).sum::<i32>(),
+                explain.total
+            );
+        }
+
+        let pos = Position::from_fen(fens[2], tables);
+        let explain = DEFAULT_PARAMS.explain(&pos);
+
+        assert_eq!(explain.terms[0].name, "captured");
+        assert_eq!(explain.terms[0].value, -4);
+        assert_eq!(explain.terms[0].contribution, -400);
+        assert_eq!(explain.total, pos.eval());
+    }
+
+    #[test]
+    fn t_weights_and_features() {
+        let tables = &TABLES;
+        let weights = (0..NUM_WEIGHTS as i32).map(|i| i * 7 % 23 - 11).collect::<Vec<_>>();
+        let params = EvalParams::default().from_weights(&weights);
+
+        assert_eq!(params.to_weights(), weights);
+        assert_eq!(params.end_scale, DEFAULT_PARAMS.end_scale);
+
+        let fens = [
+            "ec200a27ca406643 0000000000000000 1 0",
+            "ec200a27ca406643 ec100a27ca406643 2 3",
+            "ea9020804b100000 0000000000000000 1 -4",
+            "0000c00000030000 0000000000000000 2 5",
+        ];
+
+        for fen in fens.iter() {
+            let pos = Position::from_fen(fen, tables);
+            let dot = EvalParams::features(&pos)
+                .iter()
+                .map(|&(i, x)| weights[i] * x)
+                .sum::<i32>();
+
+            assert_eq!(dot, params.eval(&pos));
+        }
+    }
+}
@@ -0,0 +1,768 @@
+use crate::variant::*;
+use crate::search::Limits;
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::*;
+
+#[cfg(target_arch = "wasm32")]
+use wasm_timer::*;
+
+pub const PLAYERS: usize = 4;
+
+/// The squares of each player's quadrant, in turn order: going clockwise
+/// from the quadrant holding square 0 as the board is drawn.
+pub const QUADRANTS: [u64; PLAYERS] = [
+    0x00000000_0f0f0f0f,
+    0x00000000_f0f0f0f0,
+    0xf0f0f0f0_00000000,
+    0x0f0f0f0f_00000000,
+];
+
+/// Directions and range of pawns, drones and queens.
+const PIECES: [(&[(isize, isize)], usize); 3] = [
+    (&DIAGONAL, 1),
+    (&ORTHOGONAL, 2),
+    (&ALL_DIRECTIONS, 7),
+];
+
+/// Eval units per captured point.
+const CAPTURED: i32 = 100;
+/// Multiplier applied to the evaluation of a finished game.
+const END_SCALE: i32 = 100;
+
+/// A board of four-player Martian chess, on the full 8x8 board. Square
+/// x + 8y is bit x + 8y of both planes: a pawn sets it in the first, a drone
+/// in the second and a queen in both, so that the planes hold the value of
+/// the piece in binary as in `Board`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Board4(pub [u64; 2]);
+
+/// A move as generated, with its squares for notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move4 {
+    pub from: usize,
+    pub to: usize,
+    pub board: Board4,
+}
+
+/// The player owning `sq`.
+pub fn quadrant(sq: usize) -> usize {
+    QUADRANTS.iter().position(|q| q >> sq & 1 != 0).unwrap()
+}
+
+impl Board4 {
+    /// Every player's pieces in their corner as in the two-player game:
+    /// queens in the corner, then a diagonal of drones and pawns.
+    pub fn new() -> Self {
+        let corner = [
+            (0, 0, 3), (1, 0, 3), (0, 1, 3),
+            (2, 0, 2), (1, 1, 2), (0, 2, 2),
+            (2, 1, 1), (1, 2, 1), (2, 2, 1),
+        ];
+        let mut out = Self::empty();
+
+        for player in 0..PLAYERS {
+            for &(dx, dy, value) in corner.iter() {
+                let x = if player == 1 || player == 2 {7 - dx} else {dx};
+                let y = if player >= 2 {7 - dy} else {dy};
+
+                out.set(x + 8 * y, value);
+            }
+        }
+
+        out
+    }
+
+    pub fn empty() -> Self {
+        Board4([0, 0])
+    }
+
+    pub fn occ(&self) -> u64 {
+        self.0[0] | self.0[1]
+    }
+
+    /// The value of the piece on `sq`: 1 for a pawn, 2 for a drone, 3 for a
+    /// queen and 0 for none.
+    pub fn value(&self, sq: usize) -> u32 {
+        (self.0[0] >> sq & 1 | (self.0[1] >> sq & 1) << 1) as u32
+    }
+
+    pub fn set(&mut self, sq: usize, value: u32) {
+        self.0[0] = self.0[0] & !(1 << sq) | (value as u64 & 1) << sq;
+        self.0[1] = self.0[1] & !(1 << sq) | (value as u64 >> 1) << sq;
+    }
+
+    /// The material on the squares of `mask`.
+    pub fn material(&self, mask: u64) -> i32 {
+        ((self.0[0] & mask).count_ones() + 2 * (self.0[1] & mask).count_ones()) as i32
+    }
+
+    /// The pieces on the squares of `mask`.
+    fn part(&self, mask: u64) -> [u64; 2] {
+        [self.0[0] & mask, self.0[1] & mask]
+    }
+
+    /// The game ends when any quadrant is empty.
+    pub fn game_end(&self) -> bool {
+        QUADRANTS.iter().any(|&q| self.occ() & q == 0)
+    }
+
+    /// The moves of `player`. `prev` is the board before the last move, so
+    /// that a piece the previous player moved across a canal can't be sent
+    /// straight back.
+    pub fn gen_moves(&self, player: usize, prev: Board4, out: &mut Vec<Move4>) {
+        out.clear();
+
+        let quad = QUADRANTS[player];
+        let occ = self.occ();
+        let drones = self.0[1] & !self.0[0];
+        let queens = self.0[0] & self.0[1];
+
+        // the field promotions allowed: two pawns into a drone, a pawn and
+        // a drone into a queen
+        let promotes = [false, false, drones & quad == 0, queens & quad == 0];
+
+        for from in (0..64).filter(|&sq| (occ & quad) >> sq & 1 != 0) {
+            let value = self.value(from);
+            let (dirs, range) = PIECES[value as usize - 1];
+            let mut board = *self;
+
+            board.set(from, 0);
+
+            for &(dx, dy) in dirs.iter() {
+                let (mut x, mut y) = ((from % 8) as isize, (from / 8) as isize);
+
+                for _ in 0..range {
+                    x += dx;
+                    y += dy;
+
+                    if !(0..8).contains(&x) || !(0..8).contains(&y) {
+                        break;
+                    }
+
+                    let to = (x + 8 * y) as usize;
+                    let target = self.value(to);
+                    let mut value = value;
+
+                    if target != 0 && quad >> to & 1 != 0 {
+                        value += target;
+
+                        if value as usize >= promotes.len() || !promotes[value as usize] {
+                            break;
+                        }
+                    }
+
+                    let mut mov = board;
+                    mov.set(to, value);
+                    out.push(Move4 {from, to, board: mov});
+
+                    if target != 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let last = QUADRANTS[(player + PLAYERS - 1) % PLAYERS];
+
+        out.retain(|m| {
+            m.board.part(last) == self.part(last) || m.board.part(last) != prev.part(last)
+        });
+    }
+}
+
+impl Default for Board4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Names `sq` as in the two-player notation, with files a to h from the
+/// left of the drawn board.
+pub fn square_name4(sq: usize) -> String {
+    format!("{}{}", "hgfedcba".as_bytes()[sq % 8] as char, sq / 8 + 1)
+}
+
+pub fn stringify_move4(mov: (usize, usize)) -> String {
+    square_name4(mov.0) + &square_name4(mov.1)
+}
+
+fn parse_square4(s: &str) -> Option<usize> {
+    let mut chars = s.chars();
+    let x = "hgfedcba".find(chars.next()?.to_ascii_lowercase())?;
+    let y = "12345678".find(chars.next()?)?;
+
+    Some(x + 8 * y)
+}
+
+pub fn parse_move4(s: &str) -> Option<(usize, usize)> {
+    let s = s.trim();
+
+    if s.len() != 4 || !s.is_char_boundary(2) {
+        return None;
+    }
+
+    Some((parse_square4(&s[..2])?, parse_square4(&s[2..])?))
+}
+
+/// Draws the board with ranks down the left, files along the bottom and the
+/// canals between the quadrants.
+impl fmt::Display for Board4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for y in (0..8).rev() {
+            if y == 3 {
+                writeln!(f, "   {}", "~".repeat(27))?;
+            }
+
+            write!(f, "{}  ", y + 1)?;
+
+            for x in (0..8).rev() {
+                if x == 3 {
+                    write!(f, " ~ ")?;
+                }
+
+                write!(f, " {} ", ['.', 'p', 'd', 'q'][self.value(x + 8 * y) as usize])?;
+            }
+
+            writeln!(f)?;
+        }
+
+        write!(f, "\n   ")?;
+
+        for (i, c) in "abcdefgh".chars().enumerate() {
+            if i == 4 {
+                write!(f, "   ")?;
+            }
+            write!(f, " {} ", c)?;
+        }
+
+        writeln!(f)
+    }
+}
+
+/// What `Position4::undo_move` needs to take back a move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undo4 {
+    prev: Board4,
+    points: [i32; PLAYERS],
+}
+
+/// A four-player position: the board, the player to move and the points
+/// each player has captured. Each player owns a quadrant, and a piece
+/// belongs to whoever's quadrant it stands in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position4 {
+    pub board: Board4,
+    prev: Board4,
+    player: usize,
+    points: [i32; PLAYERS],
+}
+
+impl Position4 {
+    pub fn new() -> Self {
+        Self {
+            board: Board4::new(),
+            prev: Board4::empty(),
+            player: 0,
+            points: [0; PLAYERS],
+        }
+    }
+
+    pub fn get_player(&self) -> usize {
+        self.player
+    }
+
+    pub fn get_points(&self) -> [i32; PLAYERS] {
+        self.points
+    }
+
+    pub fn gen_moves(&self, out: &mut Vec<Move4>) {
+        self.board.gen_moves(self.player, self.prev, out)
+    }
+
+    pub fn game_end(&self) -> bool {
+        self.board.game_end()
+    }
+
+    /// Plays `board` for the player to move, who scores whatever it
+    /// captured. A field promotion keeps the material on the board, so the
+    /// material lost is what was captured.
+    pub fn do_move(&mut self, board: Board4) -> Undo4 {
+        let out = Undo4 {prev: self.prev, points: self.points};
+
+        self.points[self.player] += self.board.material(!0) - board.material(!0);
+        self.prev = self.board;
+        self.board = board;
+        self.player = (self.player + 1) % PLAYERS;
+
+        out
+    }
+
+    /// Passes the turn, for a player without moves. Nothing was moved
+    /// across a canal, so the next player may move anything.
+    pub fn pass(&mut self) -> Undo4 {
+        self.do_move(self.board)
+    }
+
+    pub fn undo_move(&mut self, undo: Undo4) {
+        self.board = self.prev;
+        self.prev = undo.prev;
+        self.points = undo.points;
+        self.player = (self.player + PLAYERS - 1) % PLAYERS;
+    }
+
+    /// Plays a move in coordinate notation such as `c3d4`, returning false
+    /// if it is not legal.
+    pub fn do_string_move(&mut self, s: &str) -> bool {
+        let mut moves = Vec::new();
+        self.gen_moves(&mut moves);
+
+        match parse_move4(s).and_then(|(from, to)| {
+            moves.iter().find(|m| (m.from, m.to) == (from, to))
+        }) {
+            Some(m) => {
+                self.do_move(m.board);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// The players with the most points.
+    pub fn leaders(&self) -> Vec<usize> {
+        let best = *self.points.iter().max().unwrap();
+
+        (0..PLAYERS).filter(|&p| self.points[p] == best).collect()
+    }
+
+    /// Evaluation for `player` under the paranoid assumption that the other
+    /// three play together against it: its points against theirs, with
+    /// the material at risk in each quadrant as a tie-break.
+    pub fn eval(&self, player: usize) -> i32 {
+        let scale = if self.game_end() {END_SCALE} else {1};
+
+        (0..PLAYERS).map(|p| {
+            let value = CAPTURED * self.points[p] * scale -
+                self.board.material(QUADRANTS[p]);
+
+            if p == player {3 * value} else {-value}
+        }).sum()
+    }
+}
+
+impl Default for Position4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Position4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "p{} to move, points {:?}", self.player + 1, self.points)?;
+        writeln!(f)?;
+        write!(f, "{}", self.board)
+    }
+}
+
+/// A paranoid alpha-beta search: the player to move at the root maximizes
+/// its evaluation and the three others are taken to minimize it together.
+pub struct Searcher4 {
+    pos: Position4,
+    root: usize,
+    nodes: usize,
+    max_nodes: usize,
+    start: Instant,
+    time: u128,
+    depth: usize,
+    stop: Arc<AtomicBool>,
+    aborted: bool,
+}
+
+impl Searcher4 {
+    pub fn new(pos: Position4) -> Self {
+        Self {
+            pos,
+            root: 0,
+            nodes: 0,
+            max_nodes: usize::MAX,
+            start: Instant::now(),
+            time: u128::MAX,
+            depth: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+            aborted: false,
+        }
+    }
+
+    pub fn get_position(&self) -> &Position4 {
+        &self.pos
+    }
+
+    pub fn set_position(&mut self, pos: Position4) {
+        self.pos = pos;
+    }
+
+    pub fn get_nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// Replaces the flag that makes a running search return early once set,
+    /// allowing another thread to stop it.
+    pub fn set_stop(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    pub fn get_stop(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Whether the search should stop. The first iteration always finishes
+    /// so that a move is found.
+    fn out_of_limits(&self) -> bool {
+        self.depth > 1 && (
+            self.start.elapsed().as_millis() >= self.time ||
+            self.nodes >= self.max_nodes ||
+            self.stop.load(Ordering::Relaxed)
+        )
+    }
+
+    /// The moves of the position, captures first and the largest first.
+    fn sorted_moves(&self) -> Vec<Move4> {
+        let mut moves = Vec::new();
+
+        self.pos.gen_moves(&mut moves);
+        moves.sort_by_key(|m| m.board.material(!0));
+        moves
+    }
+
+    /// Captures only, until the side to move would rather stand pat.
+    fn quiesce(&mut self, mut alpha: i32, mut beta: i32) -> i32 {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(1024) && self.out_of_limits() {
+            self.aborted = true;
+        }
+        if self.aborted {
+            return 0;
+        }
+
+        let stand = self.pos.eval(self.root);
+
+        if self.pos.game_end() {
+            return stand;
+        }
+
+        let maximize = self.pos.get_player() == self.root;
+
+        if maximize {
+            if stand >= beta {
+                return stand;
+            }
+            alpha = alpha.max(stand);
+        } else {
+            if stand <= alpha {
+                return stand;
+            }
+            beta = beta.min(stand);
+        }
+
+        let material = self.pos.board.material(!0);
+        let mut best = stand;
+
+        for m in self.sorted_moves().into_iter().filter(|m| m.board.material(!0) < material) {
+            let u = self.pos.do_move(m.board);
+            let score = self.quiesce(alpha, beta);
+            self.pos.undo_move(u);
+
+            if self.aborted {
+                return 0;
+            }
+            if maximize {
+                best = best.max(score);
+                alpha = alpha.max(score);
+            } else {
+                best = best.min(score);
+                beta = beta.min(score);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best
+    }
+
+    fn paranoid(&mut self, mut alpha: i32, mut beta: i32, depth: usize) -> i32 {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(1024) && self.out_of_limits() {
+            self.aborted = true;
+        }
+        if self.aborted {
+            return 0;
+        }
+
+        if self.pos.game_end() {
+            return self.pos.eval(self.root);
+        }
+        if depth == 0 {
+            return self.quiesce(alpha, beta);
+        }
+
+        let maximize = self.pos.get_player() == self.root;
+        let moves = self.sorted_moves();
+        let mut best = if maximize {i32::MIN} else {i32::MAX};
+
+        // a player without moves passes
+        if moves.is_empty() {
+            let u = self.pos.pass();
+            let score = self.paranoid(alpha, beta, depth - 1);
+            self.pos.undo_move(u);
+
+            return score;
+        }
+
+        for m in moves {
+            let u = self.pos.do_move(m.board);
+            let score = self.paranoid(alpha, beta, depth - 1);
+            self.pos.undo_move(u);
+
+            if maximize {
+                best = best.max(score);
+                alpha = alpha.max(score);
+            } else {
+                best = best.min(score);
+                beta = beta.min(score);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best
+    }
+
+    /// Searches the position within `limits`, returning the best move of
+    /// the last finished iteration and its score for the player to move.
+    /// The move is `None` if the player has to pass.
+    pub fn search(&mut self, limits: &Limits) -> (Option<Move4>, i32) {
+        let mut best = None;
+        let mut score = 0;
+
+        self.start = Instant::now();
+        self.time = limits.time as u128;
+        self.nodes = 0;
+        self.max_nodes = limits.nodes;
+        self.aborted = false;
+        self.root = self.pos.get_player();
+        self.depth = 1;
+
+        while self.depth <= limits.depth && !self.out_of_limits() && !self.pos.game_end() {
+            let mut iteration = None;
+            let mut alpha = i32::MIN;
+            let moves = self.sorted_moves();
+
+            if moves.is_empty() {
+                let u = self.pos.pass();
+                let s = self.paranoid(alpha, i32::MAX, self.depth - 1);
+                self.pos.undo_move(u);
+
+                if self.aborted {
+                    break;
+                }
+
+                score = s;
+                self.depth += 1;
+                continue;
+            }
+
+            for m in moves {
+                let u = self.pos.do_move(m.board);
+                let s = self.paranoid(alpha, i32::MAX, self.depth - 1);
+                self.pos.undo_move(u);
+
+                if self.aborted {
+                    break;
+                }
+                if iteration.is_none() || s > alpha {
+                    iteration = Some(m);
+                    alpha = s;
+                }
+            }
+
+            if self.aborted || iteration.is_none() {
+                break;
+            }
+
+            best = iteration;
+            score = alpha;
+            self.depth += 1;
+        }
+
+        (best, score)
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_start() {
+        let board = Board4::new();
+        let mut moves = Vec::new();
+
+        for (player, &quad) in QUADRANTS.iter().enumerate() {
+            assert_eq!((board.occ() & quad).count_ones(), 9);
+            assert_eq!(board.material(quad), 18);
+
+            // the quadrants are mirror images, so every player has as many
+            // moves
+            board.gen_moves(player, Board4::empty(), &mut moves);
+            assert_eq!(moves.len(), 11);
+            assert!(moves.iter().all(|m| quadrant(m.from) == player));
+        }
+
+        assert_eq!(quadrant(0), 0);
+        assert_eq!(quadrant(7), 1);
+        assert_eq!(quadrant(63), 2);
+        assert_eq!(quadrant(56), 3);
+        assert_eq!(parse_move4(&stringify_move4((9, 54))), Some((9, 54)));
+    }
+
+    #[test]
+    fn t_moves() {
+        let mut board = Board4::empty();
+        let mut moves = Vec::new();
+
+        // a pawn of player 1 by the centre, a drone of player 2 across one
+        // canal and a queen of player 3 across both
+        board.set(27, 1);
+        board.set(28, 2);
+        board.set(36, 3);
+        board.set(56, 1);
+
+        board.gen_moves(0, Board4::empty(), &mut moves);
+
+        let targets = moves.iter().map(|m| m.to).collect::<Vec<_>>();
+
+        assert_eq!(moves.len(), 4);
+        assert!(targets.contains(&36) && targets.contains(&34));
+
+        // pawn takes queen for 3 points
+        let mut pos = Position4 {board, prev: Board4::empty(), player: 0, points: [0; 4]};
+        let take = moves.iter().find(|m| m.to == 36).unwrap();
+        let undo = pos.do_move(take.board);
+
+        assert_eq!(pos.get_points(), [3, 0, 0, 0]);
+        assert_eq!(pos.get_player(), 1);
+
+        pos.undo_move(undo);
+        assert_eq!(pos.board, board);
+        assert_eq!(pos.get_points(), [0; 4]);
+
+        // a player without drones merges two pawns into one
+        let mut board = Board4::empty();
+
+        board.set(0, 1);
+        board.set(9, 1);
+        board.set(27, 1);
+        board.set(7, 2);
+        board.set(63, 1);
+        board.set(56, 1);
+
+        board.gen_moves(0, Board4::empty(), &mut moves);
+        assert!(moves.iter().any(|m| m.board.value(9) == 2 && m.board.value(0) == 0));
+
+        // the pawn player 1 moves across a canal can't go straight back
+        let mut pos = Position4 {board, prev: Board4::empty(), player: 0, points: [0; 4]};
+
+        assert!(pos.do_string_move(&stringify_move4((27, 20))));
+        assert!(!pos.do_string_move(&stringify_move4((20, 27))));
+        assert!(pos.do_string_move(&stringify_move4((20, 11))));
+        assert_eq!(pos.get_player(), 2);
+    }
+
+    #[test]
+    fn t_search4() {
+        // player 1 can take a queen or a pawn and must prefer the queen
+        let mut board = Board4::new();
+
+        board.set(27, 1);
+        board.set(36, 3);
+        board.set(34, 1);
+
+        let pos = Position4 {board, prev: Board4::empty(), player: 0, points: [0; 4]};
+
+        for depth in 1..4 {
+            let mut searcher = Searcher4::new(pos.clone());
+            let (mov, _) = searcher.search(&Limits::depth(depth));
+
+            assert_eq!(mov.unwrap().to, 36);
+        }
+
+        // a game between four copies of the engine runs to its end
+        let mut pos = Position4::new();
+
+        for _ in 0..400 {
+            if pos.game_end() {
+                break;
+            }
+
+            let mut searcher = Searcher4::new(pos.clone());
+
+            match searcher.search(&Limits::depth(1)).0 {
+                Some(m) => {pos.do_move(m.board);}
+                None => break
+            }
+        }
+
+        assert!(pos.get_points().iter().sum::<i32>() > 0);
+
+        // a stopped search still finishes its first iteration
+        let mut searcher = Searcher4::new(Position4::new());
+
+        searcher.get_stop().store(true, Ordering::Relaxed);
+
+        assert!(searcher.search(&Limits::depth(6)).0.is_some());
+        assert!(searcher.get_nodes() < 1024);
+    }
+
+    #[test]
+    fn t_pass() {
+        // player 4 just moved a piece across to player 1, who passes, so
+        // that nothing is held back from player 2 by the no-reversal rule
+        let board = Board4::new();
+        let mut moves = Vec::new();
+
+        board.gen_moves(3, Board4::empty(), &mut moves);
+
+        let prev = board;
+        let board = moves.iter().find(|m| quadrant(m.to) == 0).unwrap().board;
+        let mut pos = Position4 {board, prev, player: 0, points: [0; 4]};
+        let undo = pos.pass();
+
+        assert_eq!(pos.board, board);
+        assert_eq!(pos.get_player(), 1);
+        assert_eq!(pos.get_points(), [0; 4]);
+
+        pos.gen_moves(&mut moves);
+
+        let mut all = Vec::new();
+
+        board.gen_moves(1, Board4::empty(), &mut all);
+
+        assert_eq!(moves, all);
+
+        let mut searcher = Searcher4::new(pos.clone());
+
+        assert!(searcher.search(&Limits::depth(2)).0.is_some());
+
+        pos.undo_move(undo);
+
+        assert_eq!((pos.board, pos.prev, pos.get_player()), (board, prev, 0));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::gen_tables::*;
+use crate::board::*;
+use crate::position::*;
+use crate::rules::*;
+
+/// A game played from some starting position. Every position reached is
+/// kept so that moves can be taken back, along with the points each player
+/// has captured.
+#[derive(Clone)]
+pub struct Game {
+    positions: Vec<Position>,
+    moves: Vec<Board>,
+    points: Vec<[i32; 2]>,
+}
+
+impl Game {
+    /// Starts a game from `start`. Only the difference in points is known
+    /// for a position, so it is credited to whichever player is ahead.
+    pub fn new(start: Position) -> Self {
+        let score = start.get_score();
+
+        Self {
+            positions: vec![start],
+            moves: Vec::new(),
+            points: vec![[score.max(0), (-score).max(0)]],
+        }
+    }
+
+    pub fn start(&self) -> &Position {
+        &self.positions[0]
+    }
+
+    pub fn position(&self) -> &Position {
+        self.positions.last().unwrap()
+    }
+
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    pub fn moves(&self) -> &[Board] {
+        &self.moves
+    }
+
+    /// Points captured by player 1 and player 2.
+    pub fn points(&self) -> [i32; 2] {
+        *self.points.last().unwrap()
+    }
+
+    pub fn legal_moves(&self) -> Vec<Board> {
+        let mut out = Vec::new();
+
+        self.position().gen_moves(&mut out);
+        out
+    }
+
+    /// Plays `mov`, returning false and leaving the game unchanged if it is
+    /// not legal.
+    pub fn do_move(&mut self, mov: Board) -> bool {
+        if self.is_over() || !self.legal_moves().contains(&mov) {
+            return false;
+        }
+
+        let mut pos = self.position().clone();
+        let mut points = self.points();
+        let player = pos.get_player() as usize;
+
+        let captured = pos.board.captured_value(
+            &mov, pos.get_player(), &pos.get_tables().values);
+
+        points[player] += captured;
+        pos.do_move(mov);
+
+        self.positions.push(pos);
+        self.moves.push(mov);
+        self.points.push(points);
+
+        true
+    }
+
+    /// Plays a move in coordinate notation such as `a3b4`.
+    pub fn do_string_move(&mut self, s: &str) -> bool {
+        match parse_move(s) {
+            Some((sq1, sq2)) => {
+                let mov = self.position().board.do_move(sq1, sq2);
+                self.do_move(mov)
+            }
+            None => false
+        }
+    }
+
+    /// Takes back the last move, returning it.
+    pub fn undo(&mut self) -> Option<Board> {
+        let mov = self.moves.pop()?;
+
+        self.positions.pop();
+        self.points.pop();
+
+        Some(mov)
+    }
+
+    /// The moves played so far in coordinate notation.
+    pub fn move_strings(&self) -> Vec<String> {
+        self.positions[1..]
+            .iter()
+            .map(|pos| stringify_move(pos.get_move()))
+            .collect()
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.position().game_end()
+    }
+
+    /// The result for player 1 as it stands on points: 1 for a win, 0.5 for
+    /// a draw and 0 for a loss. A draw by repetition is a draw whatever the
+    /// points.
+    pub fn outcome(&self) -> f64 {
+        let [p1, p2] = self.points();
+
+        if self.position().is_repetition_draw() {
+            0.5
+        } else if p1 > p2 {
+            1.0
+        } else if p1 < p2 {
+            0.0
+        } else {
+            0.5
+        }
+    }
+
+    /// `outcome` if the game is over.
+    pub fn result(&self) -> Option<f64> {
+        if self.is_over() {
+            Some(self.outcome())
+        } else {
+            None
+        }
+    }
+
+    /// A single line recording the game: the starting position, the moves,
+    /// the points of each player and the result on points, separated by
+    /// semicolons.
+    pub fn to_record(&self) -> String {
+        let [p1, p2] = self.points();
+
+        format!("{} ; {} ; {} {} ; {}",
+                self.start().to_fen(),
+                self.move_strings().join(" "),
+                p1, p2,
+                result_string(self.outcome()))
+    }
+
+    /// Replays a game from `to_record`. The points and result are
+    /// recomputed from the moves rather than read back.
+    pub fn from_record(record: &str, tables: &'static Tables) -> Option<Self> {
+        Self::from_record_with(record, tables, &RuleSet::STANDARD)
+    }
+
+    /// `from_record` under `rules` rather than the standard rules.
+    pub fn from_record_with(record: &str, tables: &'static Tables, rules: &RuleSet)
+        -> Option<Self>
+    {
+        let mut fields = record.split(';');
+
+        let fen = fields.next()?.trim();
+        let moves = fields.next()?;
+
+        Self::replay(Position::parse_fen(fen, tables)?.with_rules(*rules), moves)
+    }
+
+    /// Reads a game or position in any of the formats used around the
+    /// engine: a record as written by `to_record`, a fen, a board in
+    /// `Board::to_desc` format with player 1 to move, or a list of moves from
+    /// the start position.
+    pub fn parse(s: &str, tables: &'static Tables) -> Option<Self> {
+        Self::parse_with(s, tables, &RuleSet::STANDARD)
+    }
+
+    /// `parse` under `rules` rather than the standard rules.
+    pub fn parse_with(s: &str, tables: &'static Tables, rules: &RuleSet) -> Option<Self> {
+        let s = s.trim();
+
+        if s.contains(';') {
+            return Self::from_record_with(s, tables, rules);
+        }
+        if let Some(pos) = Position::parse_fen(s, tables) {
+            return Some(Self::new(pos.with_rules(*rules)));
+        }
+        if s.contains('/') {
+            let board = Board::parse_desc(s)?;
+            let pos = Position::from_fen(&format!("{:016x} 0 1 0", board.0), tables);
+
+            return Some(Self::new(pos.with_rules(*rules)));
+        }
+
+        Self::replay(Position::new(tables).with_rules(*rules), s)
+    }
+
+    /// Plays the moves listed in `moves` from `start`.
+    fn replay(start: Position, moves: &str) -> Option<Self> {
+        let mut out = Self::new(start);
+
+        for mov in moves.split_whitespace() {
+            if !out.do_string_move(mov) {
+                return None;
+            }
+        }
+
+        Some(out)
+    }
+}
+
+/// Converts a line of moves played from `start` into coordinate notation,
+/// stopping at the first illegal move.
+pub fn line_strings(start: &Position, moves: &[Board]) -> Vec<String> {
+    let mut game = Game::new(start.clone());
+
+    for m in moves {
+        if !game.do_move(*m) {
+            break;
+        }
+    }
+
+    game.move_strings()
+}
+
+/// `1-0`, `0-1` or `1/2-1/2` for a result from player 1's point of view.
+pub fn result_string(result: f64) -> &'static str {
+    if result > 0.5 {
+        "1-0"
+    } else if result < 0.5 {
+        "0-1"
+    } else {
+        "1/2-1/2"
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_game() {
+        let tables = &TABLES;
+
+        // player 1's pawn on c4 can take the drone on b5
+        let board = Board::from_desc("q3/4/4/1d2/2p1/4/4/3q");
+        let pos = Position::from_fen(&format!("{:016x} 0 1 0", board.0), tables);
+        let mut game = Game::new(pos);
+
+        assert!(!game.do_string_move("c4c5"));
+        assert!(!game.do_string_move("c4"));
+        assert!(game.do_string_move("c4b5"));
+
+        assert_eq!(game.points(), [2, 0]);
+        assert_eq!(game.move_strings(), vec!["c4b5"]);
+        assert_eq!(game.outcome(), 1.0);
+        assert_eq!(game.result(), None);
+
+        let record = game.to_record();
+        let replayed = Game::from_record(&record, tables).unwrap();
+
+        assert_eq!(replayed.points(), [2, 0]);
+        assert_eq!(replayed.position().to_fen(), game.position().to_fen());
+        assert!(record.ends_with("; 2 0 ; 1-0"));
+
+        assert!(game.undo().is_some());
+        assert_eq!(game.points(), [0, 0]);
+        assert_eq!(game.position().board, board);
+        assert!(game.undo().is_none());
+
+        assert!(Game::from_record("nonsense", tables).is_none());
+    }
+
+    #[test]
+    fn t_parse_game() {
+        let tables = &TABLES;
+
+        let mut game = Game::new(Position::new(tables));
+        let mov = game.legal_moves()[0];
+
+        game.do_move(mov);
+
+        let moves = game.move_strings().join(" ");
+        let fen = game.position().to_fen();
+
+        for s in [moves.clone(), fen.clone(), game.to_record()].iter() {
+            assert_eq!(Game::parse(s, tables).unwrap().position().to_fen(), fen);
+        }
+
+        let desc = "q3/4/4/1d2/2p1/4/4/3q";
+        let game = Game::parse(desc, tables).unwrap();
+
+        assert_eq!(game.position().board.to_desc(), desc);
+        assert!(!game.position().get_player());
+
+        assert_eq!(Game::parse("", tables).unwrap().position().board, Board::new());
+        assert!(Game::parse("a1a1", tables).is_none());
+        assert!(Game::parse("4/4/4/4/4/4/4/ppppppp", tables).is_none());
+        assert!(Game::parse("x/y", tables).is_none());
+
+        // a pawn crossing the canal and straight back, which only rules
+        // without no-reversal allow
+        let board = Board::from_desc("3q/4/4/4/2p1/4/4/p3");
+        let record = format!("{:016x} 0 1 0 ; {} {} ; 0 0 ; 1/2-1/2",
+                             board.0, stringify_move((13, 16)), stringify_move((16, 13)));
+        let rules = RuleSet {no_reversal: false, ..RuleSet::STANDARD};
+        let game = Game::parse_with(&record, tables, &rules).unwrap();
+
+        assert!(Game::parse(&record, tables).is_none());
+        assert_eq!(game.position().board, board);
+        assert_eq!(*game.position().get_rules(), rules);
+    }
+
+    #[test]
+    fn t_parse_move() {
+        for sq1 in 0..32 {
+            for sq2 in 0..32 {
+                assert_eq!(parse_move(&stringify_move((sq1, sq2))), Some((sq1, sq2)));
+            }
+        }
+
+        assert_eq!(parse_move("e1a1"), None);
+        assert_eq!(parse_move("a9a1"), None);
+        assert_eq!(parse_move("a1"), None);
+    }
+}
@@ -1,3 +1,6 @@
+use crate::magic::*;
+use crate::variant::*;
+
 pub struct BitStack(pub u32);
 
 impl Iterator for BitStack {
@@ -69,6 +72,31 @@ pub fn stringify_move(mov: (usize, usize)) -> String {
     out
 }
 
+/// Parses one square of a move, such as `c3`.
+pub fn parse_square(s: &str) -> Option<usize> {
+    let mut chars = s.trim().chars();
+
+    let x = "dcba".find(chars.next()?)?;
+    let y = "12345678".find(chars.next()?)?;
+
+    if chars.next().is_some() {
+        return None;
+    }
+
+    Some(x + y * 4)
+}
+
+/// The inverse of `stringify_move`, or `None` if `s` is not two squares.
+pub fn parse_move(s: &str) -> Option<(usize, usize)> {
+    let s = s.trim();
+
+    if s.len() != 4 || !s.is_char_boundary(2) {
+        return None;
+    }
+
+    Some((parse_square(&s[..2])?, parse_square(&s[2..])?))
+}
+
 pub fn print_move(mov: (usize, usize)) {
     println!("{}", stringify_move(mov));
 }
@@ -105,231 +133,236 @@ pub fn print_u64(board: u64) {
     }
 }
 
-fn num_to_mask(num: u32, mask: u32) -> u32 {
-    let mut num_bit = 1;
-    let mut out = 0;
-
-    for mask_bit in BitStack(mask) {
-        if num & num_bit != 0 {
-            out |= mask_bit;
-        }
-        num_bit <<= 1;
-    }
-
-    out
+/// A magic lookup: the mask of the squares whose occupancy matters, the
+/// magic, the shift and the attacks indexed by the magic hash of the
+/// occupancy.
+pub type Magic = (u32, u32, usize, &'static [u32]);
+
+/// The move tables of a variant. For each piece, and for pawns and drones
+/// again with every blocker included for field promotions, each square has
+/// the lookups of two groups of directions. `values` are the points for
+/// capturing each piece.
+#[cfg(not(feature = "rays"))]
+#[derive(Clone, Copy)]
+pub struct Tables {
+    pub attacks: [&'static [[Magic; 2]]; 3],
+    pub field: [&'static [[Magic; 2]]; 2],
+    pub values: [i32; 3],
 }
 
-fn within_board(x: isize, y: isize) -> bool {
-    (0..4).contains(&x) && (0..8).contains(&y)
+/// The move tables of a variant with the `rays` feature. `rays` holds the
+/// squares from each square to the edge of the board in each of eight
+/// directions, and `ranged` the same cut off at the range of each piece,
+/// which moves in the directions set in its byte of `directions`. `values`
+/// are the points for capturing each piece.
+#[cfg(feature = "rays")]
+#[derive(Clone, Copy)]
+pub struct Tables {
+    pub rays: &'static [[u32; 8]],
+    pub ranged: [&'static [[u32; 8]]; 3],
+    pub directions: [u8; 3],
+    pub values: [i32; 3],
 }
 
-fn gen_att(sq: usize, dist: usize, deltas: &[(isize, isize)], board: u32, field: bool)
-    -> u32
-{
-    let startx = (sq % 4) as isize;
-    let starty = (sq / 4) as isize;
-    let mut x;
-    let mut y;
-
-    let mut i;
-    let mut out = 0;
-
-    for (dx, dy) in deltas {
-        i = 0;
-        x = startx + dx;
-        y = starty + dy;
-
-        while within_board(x, y) &&
-              i < dist &&
-              board & (1 << (x + y * 4)) == 0
-        {
-            out |= 1 << (x + y * 4);
-            x += dx;
-            y += dy;
-            i += 1;
-        }
+include!(concat!(env!("OUT_DIR"), "/tables.rs"));
 
-        if within_board(x, y) && i < dist && (field || ((y >= 4) != (starty >= 4))) {
-            out |= 1 << (x + y * 4);
-        }
-    }
-
-    out
+#[cfg(not(feature = "rays"))]
+fn magic_lookup(entries: &[Magic; 2], occ: u32) -> u32 {
+    entries.iter().fold(0, |out, (mask, magic, shift, table)| {
+        out | table[((occ & mask).wrapping_mul(*magic) >> shift) as usize]
+    })
 }
 
-fn piece_properties() -> Vec<(usize, Vec<(isize, isize)>)> {
-    vec![
-        // (1, vec![(-1, -1), (1, -1), (-1,  1), (1,  1), (-1,  0), (0, -1), ( 1,  0), (0,  1)]),
-        (1, vec![(-1, -1), (1, -1), (-1,  1), (1,  1)]),
-        (2, vec![(-1,  0), (0, -1), ( 1,  0), (0,  1)]),
-        (2, vec![(-1,  0), (0, -1), ( 1,  0), (0,  1)]),
-        (9, vec![(-1,  0), (1,  0), (-1, -1), (1, -1), (-1, 1), (1, 1)]),
-        (9, vec![( 0, -1), (0,  1)])
-    ]
+#[cfg(not(feature = "rays"))]
+fn leak_magics(table: Vec<Vec<[MagicTable; 2]>>) -> Vec<&'static [[Magic; 2]]> {
+    table.into_iter()
+        .map(|squares| {
+            let squares = squares.into_iter()
+                .map(|entries| entries.map(|(mask, magic, shift, attacks)| {
+                    (mask, magic, shift, &*attacks.leak())
+                }))
+                .collect::<Vec<_>>();
+
+            &*squares.leak()
+        })
+        .collect()
 }
 
-fn gen_masks() -> Vec<Vec<u32>> {
-    let props = piece_properties();
-    let mut out = Vec::new();
+impl Tables {
+    /// A copy of `TABLES`, which only holds references to the static data.
+    pub fn new() -> Self {
+        TABLES
+    }
 
-    for (dist, deltas) in props {
-        let mut o = Vec::new();
+    /// The tables of `variant`: `TABLES` for the standard rules, or tables
+    /// generated now, which live for the rest of the program as positions
+    /// borrow them for good.
+    pub fn from_variant(variant: &Variant) -> Result<&'static Tables, String> {
+        variant.validate()?;
 
-        for sq in 0..32 {
-            o.push(gen_att(sq, dist, &deltas, 0, false));
+        if *variant == Variant::standard() {
+            return Ok(&TABLES);
         }
 
-        out.push(o);
+        Ok(Box::leak(Box::new(Self::generate(variant))))
     }
 
-    out
-}
+    #[cfg(not(feature = "rays"))]
+    fn generate(variant: &Variant) -> Self {
+        let tables = gen_magic_tables(variant);
+        let attacks = leak_magics(tables.attacks);
+        let field = leak_magics(tables.field);
 
-fn gen_occ_att() -> Vec<Vec<Vec<(u32, u32)>>> {
-    let masks = gen_masks();
-    let props = piece_properties();
-
-    let mut out = Vec::new();
-
-    for piece in 1..5 {
-        let (dist, deltas) = &props[piece];
-        let mut pout = Vec::new();
+        Self {
+            attacks: [attacks[0], attacks[1], attacks[2]],
+            field: [field[0], field[1]],
+            values: variant.values(),
+        }
+    }
 
-        for sq in 0..32 {
-            let mask = masks[piece][sq];
-            let mut sout = vec![(0, 0); 1 << mask.count_ones()];
+    #[cfg(feature = "rays")]
+    fn generate(variant: &Variant) -> Self {
+        let ranged = |piece: usize| &*gen_rays(variant.pieces[piece].range).leak();
+        let dirs = |piece: usize| direction_mask(&variant.pieces[piece]);
 
-            for (i, s) in sout.iter_mut().enumerate() {
-                let board = num_to_mask(i as u32, mask);
-                *s = (board, gen_att(sq, *dist, deltas, board, piece == 2));
-            }
-            pout.push(sout)
+        Self {
+            rays: gen_rays(8).leak(),
+            ranged: [ranged(0), ranged(1), ranged(2)],
+            directions: [dirs(0), dirs(1), dirs(2)],
+            values: variant.values(),
         }
-
-        out.push(pout);
     }
 
-    out
-}
+    /// The squares a piece of type `piece` (0 pawn, 1 drone, 2 queen) on `sq`
+    /// attacks with occupancy `occ`, including captures across the canal.
+    #[cfg(not(feature = "rays"))]
+    pub fn attacks(&self, piece: usize, sq: usize, occ: u32) -> u32 {
+        magic_lookup(&self.attacks[piece][sq], occ)
+    }
 
-fn test_magic(table: &mut Vec<u32>,
-              changed: &mut Vec<usize>,
-              occ_att: &[(u32, u32)],
-              bits: usize,
-              magic: u32)
-    -> bool
-{
-    let mut nchanged = 0;
+    /// Like `attacks` for a pawn or drone, but every blocker is included,
+    /// for field promotions.
+    #[cfg(not(feature = "rays"))]
+    pub fn field_attacks(&self, piece: usize, sq: usize, occ: u32) -> u32 {
+        magic_lookup(&self.field[piece][sq], occ)
+    }
 
-    for (occ, att) in occ_att {
-        let ind = (occ.overflowing_mul(magic).0 >> (32 - bits)) as usize;
+    /// The squares along `rays` from `sq` in each of `dirs` up to and
+    /// including the first blocker, which is dropped unless it is across the
+    /// canal or `field` is set.
+    #[cfg(feature = "rays")]
+    fn ray_attacks(&self, rays: &[u32; 8], dirs: u8, sq: usize, occ: u32, field: bool)
+        -> u32
+    {
+        let mut out = 0;
+
+        for dir in LocStack(dirs as u32) {
+            let ray = rays[dir];
+            let blockers = ray & occ;
+
+            if blockers == 0 {
+                out |= ray;
+                continue;
+            }
 
-        changed[nchanged] = ind;
-        nchanged += 1;
+            // the nearest blocker is the lowest square for the directions
+            // that go up in square index and the highest for the others
+            let blocker = if dir < 4 {
+                blockers.trailing_zeros()
+            } else {
+                31 - blockers.leading_zeros()
+            } as usize;
 
-        if table[ind] == u32::MAX {
-            table[ind] = *att;
-        } else if table[ind] != *att {
+            let mut att = ray & !self.rays[blocker][dir];
 
-            for i in 0..nchanged {
-                table[changed[i]] = u32::MAX;
+            if !field && (blocker >= 16) == (sq >= 16) {
+                att &= !(1 << blocker);
             }
 
-            return false;
+            out |= att;
         }
-    }
-
-    true
-}
 
-fn gen_magic_table(occ_att: &[(u32, u32)], bits: usize, magic: u32)
-    -> Vec<u32>
-{
-    let size = 1 << bits;
-    let mut table = vec![0; size];
-
-    for (occ, att) in occ_att {
-        let ind = (occ.overflowing_mul(magic).0 >> (32 - bits)) as usize;
+        out
+    }
 
-        table[ind] = *att;
+    /// The squares a piece of type `piece` (0 pawn, 1 drone, 2 queen) on `sq`
+    /// attacks with occupancy `occ`, including captures across the canal.
+    #[cfg(feature = "rays")]
+    pub fn attacks(&self, piece: usize, sq: usize, occ: u32) -> u32 {
+        self.ray_attacks(&self.ranged[piece][sq], self.directions[piece], sq, occ, false)
     }
 
-    table
+    /// Like `attacks` for a pawn or drone, but every blocker is included,
+    /// for field promotions.
+    #[cfg(feature = "rays")]
+    pub fn field_attacks(&self, piece: usize, sq: usize, occ: u32) -> u32 {
+        self.ray_attacks(&self.ranged[piece][sq], self.directions[piece], sq, occ, true)
+    }
 }
 
-fn gen_magic(occ_att: &[(u32, u32)], bits: usize) -> u32 {
-    let size = 1 << bits;
-    let mut table = vec![u32::MAX; size];
-    let mut changed = vec![0; size];
-
-    loop {
-        let magic =
-            rand::random::<u32>() & rand::random::<u32>() &
-            rand::random::<u32>() & rand::random::<u32>();
-
-        if test_magic(&mut table, &mut changed, occ_att, bits, magic) {
-            return magic;
-        }
+impl Default for Tables {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[allow(clippy::type_complexity)]
-fn gen_sliding_table() -> Vec<Vec<(u32, u32, usize, Vec<u32>)>> {
-    let masks = gen_masks();
-    let occ_atts = gen_occ_att();
-    let mut out = Vec::new();
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
 
-    for piece in 1..5 {
-        let mut pout = Vec::new();
+    #[allow(dead_code)]
+    fn check_attacks(tables: &Tables, variant: &Variant) {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
 
-        for sq in 0..32 {
-            let mask = masks[piece][sq];
-            let occ_att = &occ_atts[piece - 1][sq];
-            let bits = mask.count_ones() as usize + 1;
-
-            let magic = gen_magic(occ_att, bits);
-            let table = gen_magic_table(occ_att, bits, magic);
-            pout.push((mask, magic, 32 - bits, table));
-        }
+        for i in 0..2000 {
+            let x = rng.next_u64();
 
-        out.push(pout);
-    }
+            // boards of every density, from nearly empty to nearly full
+            let occ = match i % 3 {
+                0 => x as u32 & (x >> 32) as u32,
+                1 => x as u32,
+                _ => x as u32 | (x >> 32) as u32,
+            };
 
-    out
-}
+            for sq in 0..32 {
+                let occ = occ & !(1 << sq);
 
-pub struct Tables {
-    pub pawn: Vec<u32>,
-    pub drone: Vec<(u32, u32, usize, Vec<u32>)>,
-    pub field_drone: Vec<(u32, u32, usize, Vec<u32>)>,
-    pub queen1: Vec<(u32, u32, usize, Vec<u32>)>,
-    pub queen2: Vec<(u32, u32, usize, Vec<u32>)>,
-}
+                for (piece, def) in variant.pieces.iter().enumerate() {
+                    let att = |field| gen_att(sq, def.range, &def.directions, occ, field);
 
-impl Tables {
-    pub fn new() -> Self {
-        let mut masks = gen_masks().into_iter();
-        let mut sliding = gen_sliding_table().into_iter();
+                    assert_eq!(tables.attacks(piece, sq, occ), att(false));
 
-        Self {
-            pawn: masks.next().unwrap(),
-            drone: sliding.next().unwrap(),
-            field_drone: sliding.next().unwrap(),
-            queen1: sliding.next().unwrap(),
-            queen2: sliding.next().unwrap(),
+                    if piece < 2 {
+                        assert_eq!(tables.field_attacks(piece, sq, occ), att(true));
+                    }
+                }
+            }
         }
     }
-}
 
-impl Default for Tables {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn t_attacks() {
+        // either backend gives the attacks generated square by square
+        check_attacks(&TABLES, &Variant::standard());
+
+        assert_eq!(TABLES.values, [1, 2, 3]);
+        assert!(std::ptr::eq(Tables::from_variant(&Variant::standard()).unwrap(), &TABLES));
     }
-}
 
-#[test]
-fn t_gen_tables() {
-    let tables = Tables::new();
+    #[test]
+    fn t_variant_tables() {
+        let mut variant = Variant::standard();
 
-    assert_eq!(tables.queen2.len(), 32);
+        variant.name = "kings".to_string();
+        variant.pieces[0] = PieceDef::new("king", &ALL_DIRECTIONS, 1, 2);
+        variant.pieces[1].range = 3;
+
+        let tables = Tables::from_variant(&variant).unwrap();
+
+        check_attacks(tables, &variant);
+        assert_eq!(tables.values, [2, 2, 3]);
+
+        variant.pieces[1].directions.clear();
+        assert!(Tables::from_variant(&variant).is_err());
+    }
 }
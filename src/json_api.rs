@@ -0,0 +1,409 @@
+use crate::gen_tables::*;
+use crate::position::*;
+use crate::search::*;
+use crate::game::*;
+use crate::rules::*;
+use crate::uci::*;
+
+use serde_json::{json, Map, Value};
+
+use std::io::Write;
+
+/// A scripting session speaking JSON lines: each request is an object whose
+/// `cmd` field is one of
+///
+/// - `new`: go back to the start position
+/// - `position`: set the position from `fen` (default: the start), then play
+///   the list of `moves`, if any
+/// - `legal_moves`: list the legal moves
+/// - `move`: play `move`
+/// - `undo`: take back the last move
+/// - `eval`: evaluate the position, term by term
+/// - `search`: search within `time` (milliseconds, default 1000), `depth`
+///   and `nodes` at `skill` (default the maximum)
+///
+/// `new` and `position` play by the standard rules, apart from those set in
+/// an optional `rules` object: its keys are the names of `RuleSet` fields
+/// and its values true or false, a number, or null for no limit.
+///
+/// Each reply is an object with `ok` set to true along with the results,
+/// or to false with an `error` message. An `id` field given in a request is
+/// copied to its reply. Moves are in coordinate notation, and positions are
+/// described by `position_json`.
+pub struct JsonSession {
+    tables: &'static Tables,
+    game: Game,
+    searcher: Option<Searcher>,
+}
+
+/// The fen, the board in `Board::to_desc` format, the player to move (1 or
+/// 2), the points of each player, the moves played so far and, once the game
+/// is over, its result.
+pub fn position_json(game: &Game) -> Value {
+    let pos = game.position();
+
+    json!({
+        "fen": pos.to_fen(),
+        "board": pos.board.to_desc(),
+        "player": pos.get_player() as u8 + 1,
+        "points": game.points(),
+        "moves": game.move_strings(),
+        "game_over": game.is_over(),
+        "result": game.result().map(result_string),
+    })
+}
+
+fn get_usize(req: &Map<String, Value>, key: &str) -> Result<Option<usize>, String> {
+    match req.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v.as_u64()
+            .map(|n| Some(n as usize))
+            .ok_or_else(|| format!("{} must be a non-negative integer", key)),
+    }
+}
+
+fn get_rules(req: &Map<String, Value>) -> Result<RuleSet, String> {
+    let mut rules = RuleSet::STANDARD;
+
+    match req.get("rules") {
+        None | Some(Value::Null) => {}
+        Some(Value::Object(changes)) => {
+            for (name, value) in changes {
+                let value = match value {
+                    Value::Null => "none".to_string(),
+                    Value::Bool(_) | Value::Number(_) => value.to_string(),
+                    _ => return Err(format!("bad value for {}: {}", name, value)),
+                };
+
+                rules.set(name, &value)?;
+            }
+        }
+        Some(_) => return Err("rules must be an object".to_string()),
+    }
+
+    Ok(rules)
+}
+
+fn get_str<'r>(req: &'r Map<String, Value>, key: &str) -> Result<Option<&'r str>, String> {
+    match req.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v.as_str()
+            .map(Some)
+            .ok_or_else(|| format!("{} must be a string", key)),
+    }
+}
+
+impl JsonSession {
+    pub fn new(tables: &'static Tables) -> Self {
+        Self {
+            tables,
+            game: Game::new(Position::new(tables)),
+            searcher: None,
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// Answers one request line.
+    pub fn handle(&mut self, line: &str) -> Value {
+        let req = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(req)) => req,
+            Ok(_) => return json!({"ok": false, "error": "request must be an object"}),
+            Err(e) => return json!({"ok": false, "error": format!("bad json: {}", e)}),
+        };
+
+        let mut reply = match self.dispatch(&req) {
+            Ok(mut reply) => {
+                reply.insert("ok".to_string(), Value::Bool(true));
+                reply
+            }
+            Err(e) => {
+                let mut reply = Map::new();
+                reply.insert("ok".to_string(), Value::Bool(false));
+                reply.insert("error".to_string(), Value::String(e));
+                reply
+            }
+        };
+
+        if let Some(id) = req.get("id") {
+            reply.insert("id".to_string(), id.clone());
+        }
+
+        Value::Object(reply)
+    }
+
+    fn position_reply(&self) -> Map<String, Value> {
+        let mut out = Map::new();
+
+        out.insert("position".to_string(), position_json(&self.game));
+        out
+    }
+
+    fn dispatch(&mut self, req: &Map<String, Value>) -> Result<Map<String, Value>, String> {
+        let cmd = get_str(req, "cmd")?.ok_or("missing cmd")?;
+
+        match cmd {
+            "new" => {
+                let rules = get_rules(req)?;
+
+                self.game = Game::new(Position::new(self.tables).with_rules(rules));
+                Ok(self.position_reply())
+            }
+            "position" => {
+                let mut words = match get_str(req, "fen")? {
+                    Some(fen) => {
+                        let mut words = vec!["fen"];
+                        words.extend(fen.split_whitespace());
+                        words
+                    }
+                    None => vec!["startpos"],
+                };
+
+                let moves = match req.get("moves") {
+                    None | Some(Value::Null) => Vec::new(),
+                    Some(Value::Array(moves)) => moves.iter()
+                        .map(|m| m.as_str().ok_or("moves must be strings"))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    Some(_) => return Err("moves must be a list".to_string()),
+                };
+
+                if !moves.is_empty() {
+                    words.push("moves");
+                    words.extend(moves);
+                }
+
+                self.game = parse_position(&words, self.tables, &get_rules(req)?)?;
+                Ok(self.position_reply())
+            }
+            "legal_moves" => {
+                let pos = self.game.position();
+                let moves = self.game.legal_moves()
+                    .iter()
+                    .map(|m| line_strings(pos, &[*m]).remove(0))
+                    .collect::<Vec<_>>();
+
+                let mut out = Map::new();
+                out.insert("moves".to_string(), json!(moves));
+                Ok(out)
+            }
+            "move" => {
+                let mov = get_str(req, "move")?.ok_or("missing move")?;
+
+                if self.game.is_over() {
+                    return Err("the game is over".to_string());
+                }
+                if !self.game.do_string_move(mov) {
+                    return Err(format!("illegal move: {}", mov));
+                }
+
+                Ok(self.position_reply())
+            }
+            "undo" => {
+                self.game.undo().ok_or("no move to undo")?;
+                Ok(self.position_reply())
+            }
+            "eval" => {
+                let explain = self.game.position().eval_explain();
+                let terms = explain.terms.iter()
+                    .map(|t| json!({
+                        "name": t.name,
+                        "weight": t.weight,
+                        "value": t.value,
+                        "contribution": t.contribution,
+                    }))
+                    .collect::<Vec<_>>();
+
+                let mut out = Map::new();
+                out.insert("score".to_string(), json!(explain.total));
+                out.insert("terms".to_string(), json!(terms));
+                Ok(out)
+            }
+            "search" => {
+                let limits = Limits {
+                    time: get_usize(req, "time")?.unwrap_or(1000),
+                    depth: get_usize(req, "depth")?.unwrap_or(usize::MAX).max(1),
+                    nodes: get_usize(req, "nodes")?.unwrap_or(usize::MAX),
+                };
+
+                let skill = get_usize(req, "skill")?.unwrap_or(MAX_SKILL);
+                let pos = self.game.position().clone();
+                let searcher = self.searcher
+                    .get_or_insert_with(|| Searcher::new(pos.clone()));
+
+                searcher.set_position(pos.clone());
+                searcher.set_skill(skill);
+
+                let mut last = None;
+                let (mov, score) = searcher.search_with_info(&limits, |info| {
+                    last = Some(info.clone());
+                });
+
+                let mut out = Map::new();
+
+                out.insert("move".to_string(), json!(mov.map(|m| line_strings(&pos, &[m]).remove(0))));
+                out.insert("score".to_string(), json!(score));
+                out.insert("nodes".to_string(), json!(searcher.get_nodes()));
+
+                if let Some(info) = last {
+                    out.insert("depth".to_string(), json!(info.depth));
+                    out.insert("time".to_string(), json!(info.time));
+                    out.insert("pv".to_string(), json!(line_strings(&pos, &info.pv)));
+                }
+
+                Ok(out)
+            }
+            _ => Err(format!("unknown cmd: {}", cmd)),
+        }
+    }
+}
+
+/// Answers each of `lines` with one line of JSON written to `out`, until the
+/// input ends.
+pub fn run_json<I, W>(tables: &'static Tables, lines: I, mut out: W)
+    where I: IntoIterator<Item = String>, W: Write
+{
+    let mut session = JsonSession::new(tables);
+
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = session.handle(&line);
+
+        if writeln!(out, "{}", reply).and_then(|_| out.flush()).is_err() {
+            break;
+        }
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::board::*;
+    use super::*;
+
+    #[test]
+    fn t_json_session() {
+        let tables = &TABLES;
+        let mut session = JsonSession::new(tables);
+
+        let reply = session.handle(r#"{"cmd": "legal_moves", "id": 7}"#);
+        let moves = reply["moves"].as_array().unwrap();
+
+        assert_eq!(reply["ok"], true);
+        assert_eq!(reply["id"], 7);
+        assert_eq!(moves.len(), session.game().legal_moves().len());
+
+        let mov = moves[0].as_str().unwrap().to_string();
+        let reply = session.handle(&json!({"cmd": "move", "move": mov}).to_string());
+
+        assert_eq!(reply["ok"], true);
+        assert_eq!(reply["position"]["player"], 2);
+        assert_eq!(reply["position"]["moves"], json!([mov]));
+
+        let fen = reply["position"]["fen"].as_str().unwrap().to_string();
+        let reply = session.handle(r#"{"cmd": "undo"}"#);
+
+        assert_eq!(reply["position"]["board"], Board::new().to_desc());
+        assert_eq!(session.handle(r#"{"cmd": "undo"}"#)["ok"], false);
+
+        let reply = session.handle(&json!({"cmd": "position", "fen": fen}).to_string());
+
+        assert_eq!(reply["position"]["fen"], fen.as_str());
+
+        let reply = session.handle(r#"{"cmd": "search", "depth": 2}"#);
+
+        assert_eq!(reply["ok"], true);
+        assert_eq!(reply["depth"], 2);
+        assert_eq!(reply["pv"][0], reply["move"]);
+
+        let reply = session.handle(r#"{"cmd": "eval"}"#);
+        let total = reply["terms"].as_array().unwrap()
+            .iter()
+            .map(|t| t["contribution"].as_i64().unwrap())
+            .sum::<i64>();
+
+        assert_eq!(reply["score"], total);
+
+        // a pawn crossing the canal and straight back, which only rules
+        // without no-reversal allow
+        let board = Board::from_desc("3q/4/4/4/2p1/4/4/p3");
+        let req = json!({
+            "cmd": "position",
+            "fen": format!("{:016x} 0 1 0", board.0),
+            "moves": [stringify_move((13, 16)), stringify_move((16, 13))],
+            "rules": {"no_reversal": false, "winning_lead": null},
+        });
+        let reply = session.handle(&req.to_string());
+
+        assert_eq!(reply["ok"], true);
+        assert_eq!(reply["position"]["board"], board.to_desc());
+
+        let reply = session.handle(r#"{"cmd": "new", "rules": {"field_promotion": false}}"#);
+
+        assert_eq!(reply["ok"], true);
+        assert!(!session.game().position().get_rules().field_promotion);
+
+        // a move limit ends the game after one move
+        session.handle(r#"{"cmd": "new", "rules": {"move_limit": 1}}"#);
+
+        let mov = session.handle(r#"{"cmd": "search", "depth": 1}"#)["move"].clone();
+        let reply = session.handle(&json!({"cmd": "move", "move": mov}).to_string());
+
+        assert_eq!(reply["position"]["game_over"], true);
+    }
+
+    #[test]
+    fn t_json_errors() {
+        let tables = &TABLES;
+        let mut session = JsonSession::new(tables);
+
+        for req in [
+            "not json",
+            "[1, 2]",
+            r#"{"id": 1}"#,
+            r#"{"cmd": "fly"}"#,
+            r#"{"cmd": "move", "move": "a1a1"}"#,
+            r#"{"cmd": "move", "move": 5}"#,
+            r#"{"cmd": "position", "fen": "xyz"}"#,
+            r#"{"cmd": "position", "moves": ["a1a1"]}"#,
+            r#"{"cmd": "search", "depth": -1}"#,
+            r#"{"cmd": "new", "rules": [1]}"#,
+            r#"{"cmd": "new", "rules": {"castling": true}}"#,
+            r#"{"cmd": "new", "rules": {"no_reversal": "off"}}"#,
+        ].iter() {
+            let reply = session.handle(req);
+
+            assert_eq!(reply["ok"], false, "{}", req);
+            assert!(reply["error"].is_string());
+        }
+
+        // failed requests leave the position alone
+        assert_eq!(session.game().position().board, Board::new());
+        assert!(session.game().moves().is_empty());
+    }
+
+    #[test]
+    fn t_run_json() {
+        let tables = &TABLES;
+        let input = vec![
+            r#"{"cmd": "new"}"#.to_string(),
+            String::new(),
+            r#"{"cmd": "search", "depth": 1}"#.to_string(),
+        ];
+        let mut out = Vec::new();
+
+        run_json(tables, input, &mut out);
+
+        let out = String::from_utf8(out).unwrap();
+        let replies = out.lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(replies.len(), 2);
+        assert!(replies.iter().all(|r| r["ok"] == true));
+        assert!(replies[1]["move"].is_string());
+    }
+}
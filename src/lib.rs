@@ -1,15 +1,59 @@
-#![feature(test)]
-#![feature(get_mut_unchecked)]
-
+mod variant;
+mod rules;
 mod gen_tables;
+// also run by build.rs, which uses the parts for the other backend
+#[allow(dead_code)]
+mod magic;
 mod board;
+mod render;
+mod svg;
+mod eval;
 mod position;
+mod game;
 mod search;
+mod tablebase;
+mod book;
+mod four;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod tune;
+#[cfg(not(target_arch = "wasm32"))]
+mod selfplay;
+#[cfg(not(target_arch = "wasm32"))]
+mod tournament;
+#[cfg(not(target_arch = "wasm32"))]
+mod uci;
+#[cfg(not(target_arch = "wasm32"))]
+mod json_api;
+#[cfg(not(target_arch = "wasm32"))]
+mod play;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm_api;
 
+pub use variant::*;
+pub use rules::*;
 pub use gen_tables::*;
 pub use board::*;
+pub use render::*;
+pub use svg::*;
+pub use eval::*;
 pub use position::*;
+pub use game::*;
 pub use search::*;
+pub use tablebase::*;
+pub use book::*;
+pub use four::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use tune::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use selfplay::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use tournament::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use uci::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use json_api::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use play::*;
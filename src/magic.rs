@@ -0,0 +1,319 @@
+use crate::variant::*;
+
+fn num_to_mask(num: u32, mask: u32) -> u32 {
+    let mut num_bit = 1;
+    let mut out = 0;
+
+    let mut mask = mask;
+
+    while mask != 0 {
+        let mask_bit = mask & mask.wrapping_neg();
+
+        if num & num_bit != 0 {
+            out |= mask_bit;
+        }
+        mask ^= mask_bit;
+        num_bit <<= 1;
+    }
+
+    out
+}
+
+fn within_board(x: isize, y: isize) -> bool {
+    (0..4).contains(&x) && (0..8).contains(&y)
+}
+
+pub fn gen_att(sq: usize, dist: usize, deltas: &[(isize, isize)], board: u32, field: bool)
+    -> u32
+{
+    let startx = (sq % 4) as isize;
+    let starty = (sq / 4) as isize;
+    let mut x;
+    let mut y;
+
+    let mut i;
+    let mut out = 0;
+
+    for (dx, dy) in deltas {
+        i = 0;
+        x = startx + dx;
+        y = starty + dy;
+
+        while within_board(x, y) &&
+              i < dist &&
+              board & (1 << (x + y * 4)) == 0
+        {
+            out |= 1 << (x + y * 4);
+            x += dx;
+            y += dy;
+            i += 1;
+        }
+
+        if within_board(x, y) && i < dist && (field || ((y >= 4) != (starty >= 4))) {
+            out |= 1 << (x + y * 4);
+        }
+    }
+
+    out
+}
+
+fn test_magic(table: &mut [u32],
+              changed: &mut [usize],
+              occ_att: &[(u32, u32)],
+              bits: usize,
+              magic: u32)
+    -> bool
+{
+    for (nchanged, (occ, att)) in occ_att.iter().enumerate() {
+        let ind = (occ.overflowing_mul(magic).0 >> (32 - bits)) as usize;
+
+        changed[nchanged] = ind;
+
+        if table[ind] == u32::MAX {
+            table[ind] = *att;
+        } else if table[ind] != *att {
+
+            for &i in changed[..=nchanged].iter() {
+                table[i] = u32::MAX;
+            }
+
+            return false;
+        }
+    }
+
+    true
+}
+
+fn gen_magic_table(occ_att: &[(u32, u32)], bits: usize, magic: u32)
+    -> Vec<u32>
+{
+    let size = 1 << bits;
+    let mut table = vec![0; size];
+
+    for (occ, att) in occ_att {
+        let ind = (occ.overflowing_mul(magic).0 >> (32 - bits)) as usize;
+
+        table[ind] = *att;
+    }
+
+    table
+}
+
+/// A xorshift generator with a fixed seed, so that every build finds the
+/// same magics. The tests use it for random boards.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub(crate) fn next(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+}
+
+fn gen_magic(occ_att: &[(u32, u32)], bits: usize, rng: &mut Rng) -> u32 {
+    let size = 1 << bits;
+    let mut table = vec![u32::MAX; size];
+    let mut changed = vec![0; size];
+
+    loop {
+        let magic = rng.next() & rng.next() & rng.next() & rng.next();
+
+        if test_magic(&mut table, &mut changed, occ_att, bits, magic) {
+            return magic;
+        }
+    }
+}
+
+/// A magic lookup: the mask of the squares whose occupancy matters, the
+/// magic, the shift and the attacks indexed by the hash of the occupancy.
+pub type MagicTable = (u32, u32, usize, Vec<u32>);
+
+/// The magic tables of a variant.
+pub struct MagicTables {
+    /// for each piece and square, the lookups of its two direction groups
+    pub attacks: Vec<Vec<[MagicTable; 2]>>,
+    /// the same for pawns and drones with every blocker included, for field
+    /// promotions
+    pub field: Vec<Vec<[MagicTable; 2]>>,
+}
+
+/// The directions of `piece` in two groups that each get a table, with the
+/// vertical ones apart if a single table would be too big.
+fn direction_groups(piece: &PieceDef) -> [Vec<(isize, isize)>; 2] {
+    let bits = (0..32)
+        .map(|sq| gen_att(sq, piece.range, &piece.directions, 0, false).count_ones())
+        .max()
+        .unwrap_or(0);
+
+    if bits <= 10 {
+        return [piece.directions.clone(), Vec::new()];
+    }
+
+    let (other, vertical) = piece.directions.iter().partition(|(dx, _)| *dx != 0);
+
+    [other, vertical]
+}
+
+fn gen_magic_entry(sq: usize, range: usize, deltas: &[(isize, isize)], field: bool, rng: &mut Rng)
+    -> MagicTable
+{
+    let mask = gen_att(sq, range, deltas, 0, false);
+    let occ_att = (0..1 << mask.count_ones())
+        .map(|i| {
+            let board = num_to_mask(i, mask);
+            (board, gen_att(sq, range, deltas, board, field))
+        })
+        .collect::<Vec<_>>();
+
+    let bits = mask.count_ones() as usize + 1;
+    let magic = gen_magic(&occ_att, bits, rng);
+
+    (mask, magic, 32 - bits, gen_magic_table(&occ_att, bits, magic))
+}
+
+pub fn gen_magic_tables(variant: &Variant) -> MagicTables {
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+
+    let mut gen = |piece: &PieceDef, field: bool| {
+        let groups = direction_groups(piece);
+
+        (0..32)
+            .map(|sq| {
+                [gen_magic_entry(sq, piece.range, &groups[0], field, &mut rng),
+                 gen_magic_entry(sq, piece.range, &groups[1], field, &mut rng)]
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let attacks = variant.pieces.iter().map(|p| gen(p, false)).collect();
+    let field = variant.pieces[..2].iter().map(|p| gen(p, true)).collect();
+
+    MagicTables {attacks, field}
+}
+
+fn write_list<T: std::fmt::LowerHex>(out: &mut String, values: &[T]) {
+    out.push_str("&[");
+
+    for (i, v) in values.iter().enumerate() {
+        if i % 8 == 0 {
+            out.push_str("\n        ");
+        }
+        out.push_str(&format!("{:#x}, ", v));
+    }
+
+    out.push_str("\n    ]");
+}
+
+fn write_magics(out: &mut String, table: &[[MagicTable; 2]]) {
+    out.push_str("&[\n");
+
+    for entries in table {
+        out.push_str("    [\n");
+
+        for (mask, magic, shift, attacks) in entries {
+            out.push_str(&format!("    ({:#x}, {:#x}, {}, ", mask, magic, shift));
+            write_list(out, attacks);
+            out.push_str("),\n");
+        }
+
+        out.push_str("    ],\n");
+    }
+
+    out.push_str("    ]");
+}
+
+/// The Rust source of the `TABLES` static for `variant`, which build.rs
+/// writes out for `gen_tables` to include.
+pub fn tables_source(variant: &Variant) -> String {
+    let tables = gen_magic_tables(variant);
+    let mut out = String::new();
+
+    out.push_str("// generated by build.rs from src/magic.rs\n\n");
+    out.push_str("pub static TABLES: Tables = Tables {\n    attacks: [\n");
+
+    for table in tables.attacks.iter() {
+        write_magics(&mut out, table);
+        out.push_str(",\n");
+    }
+
+    out.push_str("    ],\n    field: [\n");
+
+    for table in tables.field.iter() {
+        write_magics(&mut out, table);
+        out.push_str(",\n");
+    }
+
+    out.push_str(&format!("    ],\n    values: {:?},\n}};\n", variant.values()));
+    out
+}
+
+/// The directions of `Tables::rays`: first the four that go up in square
+/// index, then their opposites in the same order.
+pub const DIRECTIONS: [(isize, isize); 8] = [
+    (1, 0), (0, 1), (1, 1), (-1, 1),
+    (-1, 0), (0, -1), (-1, -1), (1, -1),
+];
+
+/// The squares from each square in each of `DIRECTIONS`, up to `dist`
+/// steps away.
+pub fn gen_rays(dist: usize) -> Vec<[u32; 8]> {
+    (0..32)
+        .map(|sq| {
+            let mut rays = [0; 8];
+
+            for (ray, &delta) in rays.iter_mut().zip(DIRECTIONS.iter()) {
+                // on an empty board nothing stops the ray before `dist`
+                *ray = gen_att(sq, dist, &[delta], 0, false);
+            }
+
+            rays
+        })
+        .collect()
+}
+
+/// The bits of the `DIRECTIONS` a piece moves in.
+pub fn direction_mask(piece: &PieceDef) -> u8 {
+    DIRECTIONS.iter()
+        .enumerate()
+        .filter(|(_, d)| piece.directions.contains(d))
+        .fold(0, |mask, (i, _)| mask | 1 << i)
+}
+
+fn write_rays(out: &mut String, rays: &[[u32; 8]]) {
+    out.push_str("&[\n");
+
+    for r in rays {
+        out.push_str(&format!("        [{}],\n", r.iter()
+            .map(|x| format!("{:#x}", x))
+            .collect::<Vec<_>>()
+            .join(", ")));
+    }
+
+    out.push_str("    ]");
+}
+
+/// Like `tables_source`, for the ray backend.
+pub fn rays_source(variant: &Variant) -> String {
+    let mut out = String::new();
+
+    out.push_str("// generated by build.rs from src/magic.rs\n\n");
+    out.push_str("pub static TABLES: Tables = Tables {\n    rays: ");
+    write_rays(&mut out, &gen_rays(8));
+    out.push_str(",\n    ranged: [\n");
+
+    for piece in variant.pieces.iter() {
+        write_rays(&mut out, &gen_rays(piece.range));
+        out.push_str(",\n");
+    }
+
+    out.push_str(&format!("    ],\n    directions: {:?},\n    values: {:?},\n}};\n",
+                          variant.pieces.iter().map(direction_mask).collect::<Vec<_>>(),
+                          variant.values()));
+    out
+}
@@ -1,41 +1,462 @@
-#![feature(test)]
+use martian_engine::*;
 
-mod gen_tables;
-mod board;
-mod position;
-mod search;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::iter;
+use std::mem;
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
 
-use gen_tables::*;
-use position::*;
-use search::*;
+const USAGE: &str = "\
+usage: martian_engine [COMMAND] [options]
 
-use std::io::{self, BufRead};
+commands:
+    play              play against the engine in the terminal (default)
+    uci               speak the UCI-style engine protocol on stdin and stdout
+    json              answer JSON-lines requests on stdin and stdout
+    analyze           search a position, printing each iteration
+    perft DEPTH       count the positions reachable in DEPTH moves
+    bench             search a fixed set of positions and report the speed
+    selfplay          play a game of the engine against itself
+    four              play a four-player game of the engine against itself
+    convert [INPUT]   convert positions and games, read from INPUT or one per
+                      line of stdin, between formats
+
+options:
+    --fen POSITION    start from POSITION, a fen, board, game record or list
+                      of moves (default: the start position)
+    --diagram FILE    start from the board drawn in FILE, or - for stdin, as
+                      printed by the engine
+    --to-move N       the player to move in the diagram: 1 or 2 (default 1)
+    --side SIDE       the side the human plays in play: 1, 2, both or none
+                      (default 1)
+    --time MS         time per move in milliseconds (default 1000)
+    --depth N         depth per move, or of bench (default 6)
+    --nodes N         nodes per move
+    --skill N         engine skill level from 0 to 10 (default 10)
+    --params FILE     evaluation weights
+    --hash MB         transposition table size (default 16)
+    --tablebase FILE  endgame tables written by the tablebase tool, which are
+                      solved for the standard rules without no-reversal and
+                      so need --rule no_reversal=false
+    --book FILE       opening book written by the book tool
+    --rule NAME=VALUE set one of the rules, repeated for each: field_promotion,
+                      no_reversal, end_without_moves or repetition_draw to
+                      true or false, winning_lead to a number of points, or
+                      no_capture_limit or move_limit to a number of moves,
+                      with none for no limit (default: the standard rules)
+    --max-moves N     moves before selfplay stops (default 200)
+    --divide          list the count after each move in perft
+    --plain           draw the board without color
+    --to FORMAT       output of convert: fen, board, desc, record, moves,
+                      json or svg (default fen)";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
+
+fn parse<T: std::str::FromStr>(s: String) -> T {
+    s.parse().unwrap_or_else(|_| fail(USAGE))
+}
+
+struct Options {
+    fen: Option<String>,
+    diagram: Option<String>,
+    to_move: bool,
+    human: [bool; 2],
+    time: Option<usize>,
+    depth: Option<usize>,
+    nodes: Option<usize>,
+    skill: usize,
+    params: EvalParams,
+    hash: usize,
+    tablebase: Option<Arc<Tablebase>>,
+    book: Option<Arc<Book>>,
+    rules: RuleSet,
+    max_moves: usize,
+    divide: bool,
+    plain: bool,
+    to: String,
+    args: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut out = Self {
+            fen: None,
+            diagram: None,
+            to_move: false,
+            human: [true, false],
+            time: None,
+            depth: None,
+            nodes: None,
+            skill: MAX_SKILL,
+            params: EvalParams::default(),
+            hash: 16,
+            tablebase: None,
+            book: None,
+            rules: RuleSet::STANDARD,
+            max_moves: 200,
+            divide: false,
+            plain: false,
+            to: "fen".to_string(),
+            args: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+
+            match arg.as_str() {
+                "--fen"       => out.fen = Some(value()),
+                "--diagram"   => out.diagram = Some(value()),
+                "--to-move"   => {
+                    out.to_move = match value().as_str() {
+                        "1" => false,
+                        "2" => true,
+                        _ => fail(USAGE)
+                    };
+                }
+                "--side"      => {
+                    out.human = parse_sides(&value()).unwrap_or_else(|| fail(USAGE));
+                }
+                "--time"      => out.time = Some(parse(value())),
+                "--depth"     => out.depth = Some(parse(value())),
+                "--nodes"     => out.nodes = Some(parse(value())),
+                "--skill"     => out.skill = parse(value()),
+                "--params"    => {
+                    let path = value();
+
+                    out.params = EvalParams::load(&path)
+                        .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+                }
+                "--hash"      => out.hash = parse(value()),
+                "--tablebase" => {
+                    let path = value();
+                    let tablebase = Tablebase::load(&path)
+                        .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+
+                    out.tablebase = Some(Arc::new(tablebase));
+                }
+                "--book"      => {
+                    let path = value();
+                    let book = Book::load(&path)
+                        .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+
+                    out.book = Some(Arc::new(book));
+                }
+                "--rule"      => {
+                    let rule = value();
+                    let (name, value) = rule.split_once('=').unwrap_or_else(|| fail(USAGE));
+
+                    out.rules.set(name, value).unwrap_or_else(|e| fail(&e));
+                }
+                "--max-moves" => out.max_moves = parse(value()),
+                "--divide"    => out.divide = true,
+                "--plain"     => out.plain = true,
+                "--to"        => out.to = value(),
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0)
+                }
+                _ if arg.starts_with("--") => fail(USAGE),
+                _ => out.args.push(arg),
+            }
+        }
+
+        if out.tablebase.is_some() && out.rules != TABLEBASE_RULES {
+            fail("--tablebase needs --rule no_reversal=false and the other rules left as standard");
+        }
+
+        out
+    }
+
+    /// The limits given, or one second per move if there are none.
+    fn limits(&self) -> Limits {
+        if self.time.is_none() && self.depth.is_none() && self.nodes.is_none() {
+            return Limits::time(1000);
+        }
+
+        Limits {
+            time: self.time.unwrap_or(usize::MAX),
+            depth: self.depth.unwrap_or(usize::MAX),
+            nodes: self.nodes.unwrap_or(usize::MAX),
+        }
+    }
+
+    fn game(&self, tables: &'static Tables) -> Game {
+        if let Some(path) = &self.diagram {
+            let text = if path == "-" {
+                io::read_to_string(io::stdin())
+            } else {
+                fs::read_to_string(path)
+            };
+            let text = text.unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+
+            return Position::from_diagram(&text, self.to_move, tables)
+                .map(|pos| Game::new(pos.with_rules(self.rules)))
+                .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+        }
+
+        match &self.fen {
+            Some(fen) => Game::parse_with(fen, tables, &self.rules)
+                .unwrap_or_else(|| fail(&format!("bad position: {}", fen))),
+            None => Game::new(Position::new(tables).with_rules(self.rules)),
+        }
+    }
+
+    fn searcher(&self, pos: Position) -> Searcher {
+        let mut out = Searcher::new(pos);
+
+        out.set_hash_size(self.hash);
+        out.set_skill(self.skill);
+        out.set_params(self.params.clone());
+        out.set_tablebase(self.tablebase.clone());
+        out.set_book(self.book.clone());
+        out
+    }
+}
 
 fn main() {
-    let stdin = io::stdin();
-    let mut in_lines = stdin.lock().lines();
+    let mut args = env::args().skip(1).peekable();
 
-    let tables = Tables::new();
-    let mut pos = Position::new(&tables);
-    // let mut pos = Position::from_fen("ec200a27ca406643 ec100a27ca406643 1 0", &tables);
+    let command = match args.peek() {
+        Some(arg) if !arg.starts_with("--") => args.next().unwrap(),
+        _ => "play".to_string(),
+    };
 
-    while !pos.board.game_end() {
-        println!("{}", pos.to_fen());
-        println!("{}", pos);
+    let options = Options::parse(args);
+    let tables = &TABLES;
 
-        while !pos.do_string_move(&in_lines.next().unwrap().unwrap()) {}
+    match command.as_str() {
+        "play"     => play(&options, tables),
+        "uci"      => run_uci(tables, stdin_lines(), io::stdout()),
+        "json"     => run_json(tables, stdin_lines(), io::stdout()),
+        "analyze"  => analyze(&options, tables),
+        "perft"    => perft(&options, tables),
+        "bench"    => bench_command(&options, tables),
+        "selfplay" => selfplay(&options, tables),
+        "four"     => four(&options),
+        "convert"  => convert(&options, tables),
+        _ => fail(USAGE)
+    }
+}
 
-        println!("{}", pos.to_fen());
-        println!("{}", pos);
+fn stdin_lines() -> impl Iterator<Item = String> {
+    io::stdin().lock().lines().map_while(Result::ok)
+}
 
-        let mut searcher = Searcher::new(pos);
-        let (mov, score) = searcher.ab_search(1000);
+fn print_game(game: &Game) {
+    let [p1, p2] = game.points();
 
-        println!("{}", score);
+    println!("{}", game.position().to_fen());
+    println!("{}", game.position());
+    println!("points: p1 {}  p2 {}", p1, p2);
+}
 
-        pos = searcher.into_position(); 
-        pos.do_move(mov.unwrap());
+fn play(options: &Options, tables: &'static Tables) {
+    let game = options.game(tables);
+    let searcher = options.searcher(game.position().clone());
+    let engine = [!options.human[0], !options.human[1]];
+    let mut session = PlaySession::new(game, searcher, options.limits(), engine);
+
+    session.set_color(
+        !options.plain && io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()
+    );
+    let mut lines = stdin_lines();
+    let mut first = true;
+    let mut out = io::stdout();
+
+    println!("skill {} (rating ~{}), type help for commands",
+             options.skill, Skill::level(options.skill).rating);
+
+    // stop quietly once stdout is closed
+    if session.show(&mut out).is_err() {
+        return;
+    }
+
+    loop {
+        if session.engine_to_move() {
+            if session.engine_move(&mut out).is_err() {
+                break;
+            }
+            continue;
+        }
+        if session.game().is_over() && engine == [true, true] {
+            break;
+        }
+
+        let line = match lines.next() {
+            Some(line) => line,
+            None => break
+        };
+
+        // a GUI or script speaking the engine protocol or JSON lines
+        if mem::replace(&mut first, false) && options.fen.is_none() && options.diagram.is_none() {
+            if line.trim() == "uci" {
+                run_uci(tables, iter::once(line).chain(lines), out);
+                return;
+            }
+            if line.trim_start().starts_with('{') {
+                run_json(tables, iter::once(line).chain(lines), out);
+                return;
+            }
+        }
+
+        if !session.command(&line, &mut out).unwrap_or(false) {
+            break;
+        }
+    }
+}
+
+fn analyze(options: &Options, tables: &'static Tables) {
+    let game = options.game(tables);
+    let pos = game.position().clone();
+    let mut searcher = options.searcher(pos.clone());
+
+    print_game(&game);
+    println!("{}", pos.eval_explain_with(&options.params));
+
+    let (mov, score) = searcher.search_with_info(&options.limits(), |info| {
+        println!("depth {:2}  score {:6}  nodes {:10}  time {:6}  pv {}",
+                 info.depth, info.score, info.nodes, info.time,
+                 line_strings(&pos, &info.pv).join(" "));
+    });
+
+    match mov {
+        Some(m) => println!("best move {} (score {})", line_strings(&pos, &[m])[0], score),
+        None => println!("no legal moves"),
+    }
+}
+
+fn perft(options: &Options, tables: &'static Tables) {
+    let depth = match options.args.first() {
+        Some(d) => parse(d.clone()),
+        None => fail(USAGE)
+    };
+
+    let mut pos = options.game(tables).position().clone();
+    let now = Instant::now();
+
+    let total = if options.divide && depth > 0 && !pos.game_end() {
+        let mut moves = Vec::new();
+        let mut total = 0;
+
+        pos.gen_moves(&mut moves);
+
+        for m in moves {
+            let name = line_strings(&pos, &[m]).remove(0);
+            let u = pos.do_move(m);
+            let count = pos.perft(depth - 1);
+
+            pos.undo_move(u);
+            total += count;
+
+            println!("{} {}", name, count);
+        }
+
+        total
+    } else {
+        pos.perft(depth)
+    };
+
+    let ms = now.elapsed().as_millis().max(1);
+
+    println!("perft {} = {}  ({} ms, {} positions/s)", depth, total, ms, total as u128 * 1000 / ms);
+}
+
+fn bench_command(options: &Options, tables: &'static Tables) {
+    let depth = options.depth.unwrap_or(6);
+    let now = Instant::now();
+    let nodes = bench(tables, depth);
+    let ms = now.elapsed().as_millis().max(1);
+
+    println!("{} positions to depth {}: {} nodes  {} ms  {} nps",
+             BENCH_POSITIONS.len(), depth, nodes, ms, nodes as u128 * 1000 / ms);
+}
+
+fn selfplay(options: &Options, tables: &'static Tables) {
+    let start = options.game(tables).position().clone();
+    let mut p1 = options.searcher(start.clone());
+    let mut p2 = options.searcher(start.clone());
+
+    let game = play_game(start, [&mut p1, &mut p2], &options.limits(), options.max_moves);
+
+    for (i, mov) in game.move_strings().chunks(2).enumerate() {
+        println!("{:3}. {}", i + 1, mov.join(" "));
+    }
+
+    println!();
+    print_game(&game);
+    println!("{}", game.to_record());
+}
+
+fn four(options: &Options) {
+    let mut pos = Position4::new();
+    let mut moves = Vec::new();
+
+    while moves.len() < options.max_moves && !pos.game_end() {
+        let mut searcher = Searcher4::new(pos.clone());
+
+        match searcher.search(&options.limits()).0 {
+            Some(m) => {
+                pos.do_move(m.board);
+                moves.push(stringify_move4((m.from, m.to)));
+            }
+            None => {
+                pos.pass();
+                moves.push("pass".to_string());
+            }
+        }
+    }
+
+    for (i, round) in moves.chunks(4).enumerate() {
+        println!("{:3}. {}", i + 1, round.join(" "));
+    }
+
+    let leaders = pos.leaders().iter().map(|p| format!("p{}", p + 1)).collect::<Vec<_>>();
+
+    println!();
+    println!("{}", pos);
+    println!("leading: {}", leaders.join(" "));
+}
+
+fn convert(options: &Options, tables: &'static Tables) {
+    let inputs: Box<dyn Iterator<Item = String>> = if options.args.is_empty() {
+        Box::new(stdin_lines())
+    } else {
+        Box::new(iter::once(options.args.join(" ")))
+    };
+
+    let mut ok = true;
+
+    for input in inputs {
+        let game = match Game::parse_with(&input, tables, &options.rules) {
+            Some(game) => game,
+            None => {
+                eprintln!("bad position: {}", input);
+                ok = false;
+                continue;
+            }
+        };
+
+        let pos = game.position();
+
+        match options.to.as_str() {
+            "fen"    => println!("{}", pos.to_fen()),
+            "board"  => println!("{}", pos),
+            "desc"   => println!("{}", pos.board.to_desc()),
+            "record" => println!("{}", game.to_record()),
+            "moves"  => println!("{}", game.move_strings().join(" ")),
+            "json"   => println!("{}", position_json(&game)),
+            "svg"    => print!("{}", position_svg(pos, &SvgOptions::default())),
+            _ => fail(USAGE)
+        }
+    }
 
-        print_move(pos.get_move());
+    if !ok {
+        process::exit(1);
     }
 }
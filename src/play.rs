@@ -0,0 +1,336 @@
+use crate::gen_tables::*;
+use crate::position::*;
+use crate::render::*;
+use crate::search::*;
+use crate::game::*;
+
+use std::fs;
+use std::io::{self, Write};
+
+pub const PLAY_HELP: &str = "\
+commands:
+    MOVE             play a move in coordinate notation, such as c3b4
+    moves [SQUARE]   list the legal moves, or show those of the piece on
+                     SQUARE
+    hint             suggest a move
+    go               let the engine move now
+    undo             take back your last move
+    eval             show the evaluation term by term
+    flip             turn the board around
+    fen              show the position as a fen
+    load POSITION    start from a fen, board, game record or list of moves,
+                     or from the first line of a file holding one
+    save FILE        append the game record to FILE
+    new              start a new game
+    engine SIDE      let the engine play SIDE: 1, 2, both or none
+    help             show this message
+    quit             leave";
+
+/// A game against the engine in the terminal. The driver asks
+/// `engine_to_move` after each command and calls `engine_move` while it is
+/// true, passing everything else the user types to `command`.
+pub struct PlaySession {
+    tables: &'static Tables,
+    game: Game,
+    searcher: Searcher,
+    limits: Limits,
+    /// which players the engine plays
+    engine: [bool; 2],
+    render: RenderOptions,
+}
+
+/// Parses a side as given to `engine` or `--side`: `1`, `2`, `both` or
+/// `none`.
+pub fn parse_sides(s: &str) -> Option<[bool; 2]> {
+    match s {
+        "1"    => Some([true, false]),
+        "2"    => Some([false, true]),
+        "both" => Some([true, true]),
+        "none" => Some([false, false]),
+        _ => None
+    }
+}
+
+impl PlaySession {
+    pub fn new(game: Game, searcher: Searcher, limits: Limits, engine: [bool; 2]) -> Self {
+        Self {
+            tables: game.position().get_tables(),
+            game,
+            searcher,
+            limits,
+            engine,
+            render: RenderOptions {
+                // show the board from the human's side
+                flipped: engine == [true, false],
+                ..RenderOptions::default()
+            },
+        }
+    }
+
+    /// Turns ANSI colors on or off.
+    pub fn set_color(&mut self, color: bool) {
+        self.render.color = color;
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn engine_to_move(&self) -> bool {
+        !self.game.is_over() && self.engine[self.game.position().get_player() as usize]
+    }
+
+    /// Writes the board with the last move highlighted and `marks` marked,
+    /// the points of each player and whose move it is, or the result once
+    /// the game is over.
+    fn show_marked(&self, marks: u32, out: &mut impl Write) -> io::Result<()> {
+        let pos = self.game.position();
+        let [p1, p2] = self.game.points();
+        let options = RenderOptions {
+            last_move: if self.game.moves().is_empty() {None} else {Some(pos.get_move())},
+            marks,
+            ..self.render
+        };
+
+        writeln!(out, "{}", render(&pos.board, &options))?;
+        writeln!(out, "points: p1 {}  p2 {}", p1, p2)?;
+
+        if self.game.is_over() {
+            writeln!(out, "game over: {}", result_string(self.game.outcome()))
+        } else {
+            writeln!(out, "player {} to move", pos.get_player() as usize + 1)
+        }
+    }
+
+    pub fn show(&self, out: &mut impl Write) -> io::Result<()> {
+        self.show_marked(0, out)
+    }
+
+    fn search(&mut self) -> (Option<String>, i32) {
+        let pos = self.game.position().clone();
+
+        self.searcher.set_position(pos.clone());
+
+        let (mov, score) = self.searcher.search(&self.limits);
+
+        (mov.map(|m| line_strings(&pos, &[m]).remove(0)), score)
+    }
+
+    /// Lets the engine play a move for the side to move.
+    pub fn engine_move(&mut self, out: &mut impl Write) -> io::Result<()> {
+        match self.search() {
+            (Some(mov), score) => {
+                self.game.do_string_move(&mov);
+                writeln!(out, "engine plays {} (score {})", mov, score)?;
+                self.show(out)
+            }
+            (None, _) => writeln!(out, "no legal moves"),
+        }
+    }
+
+    /// Carries out one line typed by the user, returning false on `quit`.
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let line = line.trim();
+        let (cmd, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+
+        match cmd {
+            "" => {}
+            "quit" | "exit" => return Ok(false),
+            "help" => writeln!(out, "{}", PLAY_HELP)?,
+            "moves" => {
+                let pos = self.game.position();
+                let mut moves = self.game.legal_moves()
+                    .iter()
+                    .map(|m| line_strings(pos, &[*m]).remove(0))
+                    .collect::<Vec<_>>();
+
+                if arg.is_empty() {
+                    writeln!(out, "{}", moves.join(" "))?;
+                } else if let Some(sq) = parse_square(arg) {
+                    moves.retain(|m| m.starts_with(arg));
+
+                    self.show_marked(pos.gen_piece_moves(sq), out)?;
+                    writeln!(out, "{}", moves.join(" "))?;
+                } else {
+                    writeln!(out, "bad square: {}", arg)?;
+                }
+            }
+            "hint" => match self.search() {
+                (Some(mov), score) => writeln!(out, "hint: {} (score {})", mov, score)?,
+                (None, _) => writeln!(out, "no legal moves")?,
+            },
+            "go" => {
+                if !self.game.is_over() {
+                    self.engine_move(out)?;
+                }
+            }
+            "undo" => {
+                // take back the engine's reply too, so that it is the user's
+                // move again
+                let mut undone = self.game.undo().is_some();
+
+                while undone && self.engine_to_move() && self.engine != [true, true] {
+                    undone = self.game.undo().is_some();
+                }
+
+                if !undone {
+                    writeln!(out, "no move to undo")?;
+                }
+                self.show(out)?;
+            }
+            "eval" => writeln!(out, "{}", self.game.position().eval_explain_with(self.searcher.get_params()))?,
+            "flip" => {
+                self.render.flipped = !self.render.flipped;
+                self.show(out)?;
+            }
+            "fen" => writeln!(out, "{}", self.game.position().to_fen())?,
+            "load" => {
+                let text = fs::read_to_string(arg)
+                    .ok()
+                    .and_then(|t| t.lines().map(|l| l.to_string()).find(|l| !l.trim().is_empty()))
+                    .unwrap_or_else(|| arg.to_string());
+
+                let rules = *self.game.position().get_rules();
+
+                match Game::parse_with(&text, self.tables, &rules) {
+                    Some(game) => {
+                        self.game = game;
+                        self.show(out)?;
+                    }
+                    None => writeln!(out, "bad position: {}", arg)?,
+                }
+            }
+            "save" => {
+                let saved = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(arg)
+                    .and_then(|mut f| writeln!(f, "{}", self.game.to_record()));
+
+                match saved {
+                    Ok(()) => writeln!(out, "saved to {}", arg)?,
+                    Err(e) => writeln!(out, "{}: {}", arg, e)?,
+                }
+            }
+            "new" => {
+                let rules = *self.game.position().get_rules();
+
+                self.game = Game::new(Position::new(self.tables).with_rules(rules));
+                self.show(out)?;
+            }
+            "engine" => match parse_sides(arg) {
+                Some(engine) => {
+                    self.engine = engine;
+                    writeln!(out, "engine plays {}", arg)?;
+                }
+                None => writeln!(out, "engine plays 1, 2, both or none")?,
+            },
+            _ => {
+                if self.game.is_over() {
+                    writeln!(out, "the game is over; try undo, new or load")?;
+                } else if self.game.do_string_move(line) {
+                    self.show(out)?;
+                } else {
+                    writeln!(out, "illegal move or unknown command: {} (try help)", line)?;
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::board::*;
+    use super::*;
+
+    #[allow(dead_code)]
+    fn session(tables: &'static Tables) -> PlaySession {
+        let game = Game::new(Position::new(tables));
+        let mut searcher = Searcher::new(game.position().clone());
+
+        searcher.set_hash_entries(1000);
+
+        PlaySession::new(game, searcher, Limits::depth(1), [false, true])
+    }
+
+    #[allow(dead_code)]
+    fn run(session: &mut PlaySession, line: &str) -> String {
+        let mut out = Vec::new();
+
+        assert!(session.command(line, &mut out).unwrap());
+
+        while session.engine_to_move() {
+            session.engine_move(&mut out).unwrap();
+        }
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn t_play_session() {
+        let tables = &TABLES;
+        let mut session = session(tables);
+
+        let moves = run(&mut session, "moves");
+        let mov = moves.split_whitespace().next().unwrap().to_string();
+
+        assert_eq!(moves.split_whitespace().count(), session.game().legal_moves().len());
+
+        let out = run(&mut session, &mov);
+
+        assert!(out.contains("engine plays"));
+        assert!(out.contains("points: p1 0  p2 0"));
+        assert_eq!(session.game().moves().len(), 2);
+
+        // undo takes back the engine's reply as well
+        run(&mut session, "undo");
+
+        assert!(session.game().moves().is_empty());
+        assert!(run(&mut session, "undo").contains("no move to undo"));
+
+        assert!(run(&mut session, "hint").starts_with("hint: "));
+
+        let out = run(&mut session, &format!("moves {}", &mov[..2]));
+
+        // the destinations are marked on the board
+        assert!(out.contains('('));
+        assert!(out.lines().last().unwrap().split(' ').any(|m| m == mov));
+        assert!(run(&mut session, "moves z9").contains("bad square"));
+        assert!(session.game().moves().is_empty());
+
+        assert!(run(&mut session, "a1a1").contains("illegal move"));
+        assert!(run(&mut session, "engine both2").contains("1, 2, both or none"));
+
+        let fen = "ec200a27ca406643 0000000000000000 2 3 5 12";
+
+        run(&mut session, "engine none");
+        run(&mut session, &format!("load {}", fen));
+
+        assert_eq!(run(&mut session, "fen").trim(), fen);
+
+        run(&mut session, "new");
+
+        assert_eq!(session.game().position().board, Board::new());
+
+        let mut out = Vec::new();
+
+        assert!(!session.command("quit", &mut out).unwrap());
+    }
+
+    #[test]
+    fn t_flip() {
+        let tables = &TABLES;
+        let mut session = session(tables);
+
+        let before = run(&mut session, "flip");
+        let after = run(&mut session, "flip");
+
+        assert!(before.contains("8  ") && after.contains("8  "));
+        assert_ne!(before, after);
+    }
+}
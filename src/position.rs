@@ -1,13 +1,27 @@
 use crate::gen_tables::*;
 use crate::board::*;
+use crate::rules::*;
+use crate::eval::*;
 
 #[derive(Clone)]
-pub struct Position<'a> {
+pub struct Position {
     pub board: Board,
     prev: Board,
-    tables: &'a Tables,
+    tables: &'static Tables,
+    rules: RuleSet,
     player: bool,
-    score: i32
+    score: i32,
+    quiet_moves: u32,
+    move_count: u32,
+    /// the keys of the positions before each move, as far back as known
+    history: Vec<u64>
+}
+
+/// What `undo_move` needs to take back a move, as returned by `do_move`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undo {
+    prev: Board,
+    quiet_moves: u32
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -19,21 +33,30 @@ pub struct JsPosition {
     pub board: JsBoard,
     pub prev: JsBoard,
     pub player: bool,
-    pub score: i32
+    pub score: i32,
+    pub quiet_moves: u32,
+    pub move_count: u32
 }
 
 #[allow(dead_code)]
-impl<'a> Position<'a> {
-    pub fn new(tables: &'a Tables) -> Self {
+impl Position {
+    pub fn new(tables: &'static Tables) -> Self {
         Self {
             board: Board::new(),
             prev: Board::empty(),
             tables,
+            rules: RuleSet::STANDARD,
             player: false,
-            score: 0
+            score: 0,
+            quiet_moves: 0,
+            move_count: 0,
+            history: Vec::new()
         }
     }
 
+    /// The fen of the position: the board and the previous board in hex,
+    /// the player to move, the score, the moves since the last capture and
+    /// the moves played.
     pub fn to_fen(&self) -> String {
         let mut out = String::new();
 
@@ -50,12 +73,22 @@ impl<'a> Position<'a> {
 
         out += " ";
         out += &format!("{}", self.score);
+        out += &format!(" {} {}", self.quiet_moves, self.move_count);
 
         out
     }
 
+    /// How many of the words at the start of `words` make up a fen: six,
+    /// or four for fens from before the move counters, which count from 0.
+    pub fn fen_words(words: &[&str]) -> usize {
+        let counters = words.len() >= 6 &&
+            words[4..6].iter().all(|w| w.parse::<u32>().is_ok());
+
+        if counters {6} else {4}
+    }
+
     #[allow(dead_code)]
-    pub fn from_fen(fen: &str, tables: &'a Tables) -> Self {
+    pub fn from_fen(fen: &str, tables: &'static Tables) -> Self {
         let mut words = fen.split(' ');
         let mut out = Position::new(tables);
 
@@ -70,37 +103,73 @@ impl<'a> Position<'a> {
         out.player = player as usize - 49 != 0;
         out.score = words.next().unwrap().parse::<i32>().unwrap();
 
+        if let Some(quiet_moves) = words.next() {
+            out.quiet_moves = quiet_moves.parse::<u32>().unwrap();
+            out.move_count = words.next().unwrap().parse::<u32>().unwrap();
+        }
+
         out
     }
 
+    /// Like `from_fen`, but returns `None` for malformed input instead of
+    /// panicking.
+    pub fn parse_fen(fen: &str, tables: &'static Tables) -> Option<Self> {
+        let words = fen.split_whitespace().collect::<Vec<_>>();
+
+        if words.len() < 4 || Self::fen_words(&words) != words.len() ||
+           words[..2].iter().any(|w| u64::from_str_radix(w, 16).is_err()) ||
+           !matches!(words[2], "1" | "2") ||
+           words[3].parse::<i32>().is_err()
+        {
+            return None;
+        }
+
+        Some(Self::from_fen(&words.join(" "), tables))
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub fn to_js(&self) -> JsPosition {
         JsPosition {
             board: self.board.to_js(),
             prev: self.prev.to_js(),
             player: self.player,
-            score: self.score
+            score: self.score,
+            quiet_moves: self.quiet_moves,
+            move_count: self.move_count
         }
     }
 
     #[cfg(target_arch = "wasm32")]
-    pub fn from_js(tables: &'a Tables, jspos: JsPosition) -> Self {
+    pub fn from_js(tables: &'static Tables, jspos: JsPosition) -> Self {
         Self {
             board: Board::from_js(jspos.board),
             prev: Board::from_js(jspos.prev),
             tables,
+            rules: RuleSet::STANDARD,
             player: jspos.player,
-            score: jspos.score
+            score: jspos.score,
+            quiet_moves: jspos.quiet_moves,
+            move_count: jspos.move_count,
+            history: Vec::new()
         }
     }
 
-    pub fn do_move(&mut self, new_board: Board) -> Board {
-        let out = self.prev;
+    pub fn do_move(&mut self, new_board: Board) -> Undo {
+        let out = Undo {prev: self.prev, quiet_moves: self.quiet_moves};
+        self.history.push(self.key());
         self.prev = self.board;
         self.board = new_board;
 
-        let dscore =
-            (self.prev.pieces_value() - self.board.pieces_value()).max(0);
+        // merging pieces keeps the encoded material and capturing loses some
+        if new_board.pieces_value() < self.prev.pieces_value() {
+            self.quiet_moves = 0;
+        } else {
+            self.quiet_moves += 1;
+        }
+        self.move_count += 1;
+
+        let dscore = self.prev.captured_value(
+            &self.board, self.player, &self.tables.values);
 
         if self.player {
             self.score -= dscore;
@@ -113,13 +182,16 @@ impl<'a> Position<'a> {
         out
     }
 
-    pub fn undo_move(&mut self, prev: Board) -> Board {
-        let dscore =
-            (self.prev.pieces_value() - self.board.pieces_value()).max(0);
+    pub fn undo_move(&mut self, undo: Undo) -> Board {
+        let dscore = self.prev.captured_value(
+            &self.board, !self.player, &self.tables.values);
 
         let out = self.board;
         self.board = self.prev;
-        self.prev = prev;
+        self.prev = undo.prev;
+        self.quiet_moves = undo.quiet_moves;
+        self.move_count -= 1;
+        self.history.pop();
 
         self.player = !self.player;
 
@@ -179,17 +251,141 @@ impl<'a> Position<'a> {
     }
 
     pub fn eval(&self) -> i32 {
-        self.negate_if_player(
-            self.score * 100 + self.board.player_value()
-        )
+        DEFAULT_PARAMS.eval(self)
+    }
+
+    pub fn eval_with(&self, params: &EvalParams) -> i32 {
+        params.eval(self)
+    }
+
+    pub fn eval_explain(&self) -> EvalExplain {
+        DEFAULT_PARAMS.explain(self)
+    }
+
+    pub fn eval_explain_with(&self, params: &EvalParams) -> EvalExplain {
+        params.explain(self)
+    }
+
+    /// The board before the last move, or an empty board if there was none.
+    pub fn get_prev(&self) -> Board {
+        self.prev
     }
 
     pub fn get_player(&self) -> bool {
         self.player
     }
 
+    pub fn get_tables(&self) -> &'static Tables {
+        self.tables
+    }
+
+    /// Moves by either player since the last capture.
+    pub fn get_quiet_moves(&self) -> u32 {
+        self.quiet_moves
+    }
+
+    /// Moves by either player since the start of the game.
+    pub fn get_move_count(&self) -> u32 {
+        self.move_count
+    }
+
+    /// A hash of the board and the player to move, for telling cheaply
+    /// whether a position repeats an earlier one.
+    pub fn key(&self) -> u64 {
+        // the finalizer of splitmix64, so that any two boards are about as
+        // unlikely to differ by exactly the player's constant
+        let mut key = self.board.0;
+
+        key = (key ^ key >> 30).wrapping_mul(0xbf58476d1ce4e5b9);
+        key = (key ^ key >> 27).wrapping_mul(0x94d049bb133111eb);
+        key ^= key >> 31;
+
+        if self.player {
+            key ^= 0x9e3779b97f4a7c15;
+        }
+
+        key
+    }
+
+    /// How many times the position occurred before in the game. Only the
+    /// moves since the last capture are looked at, since a capture can't
+    /// be undone.
+    pub fn repetitions(&self) -> usize {
+        let len = self.history.len();
+        let back = len.min(self.quiet_moves as usize);
+        let key = self.key();
+
+        self.history[len - back..]
+            .iter()
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .filter(|&&k| k == key)
+            .count()
+    }
+
+    /// Whether the game ended in a draw under the threefold repetition rule.
+    pub fn is_repetition_draw(&self) -> bool {
+        self.rules.repetition_draw && self.repetitions() >= 2
+    }
+
+    /// Moves until the game runs into the no-capture limit or the move
+    /// limit, if the rules have either.
+    pub fn moves_left(&self) -> Option<u32> {
+        let quiet = self.rules.no_capture_limit
+            .map(|n| n.saturating_sub(self.quiet_moves));
+        let total = self.rules.move_limit
+            .map(|n| n.saturating_sub(self.move_count));
+
+        match (quiet, total) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        }
+    }
+
+    pub fn get_rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+    }
+
+    /// The position with `rules` in place of its own.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Whether the game is over under the position's rules.
+    pub fn game_end(&self) -> bool {
+        if self.board.game_end() {
+            return true;
+        }
+
+        if let Some(lead) = self.rules.winning_lead {
+            if self.score.abs() >= lead {
+                return true;
+            }
+        }
+
+        if self.moves_left() == Some(0) || self.is_repetition_draw() {
+            return true;
+        }
+
+        if self.rules.end_without_moves {
+            let mut moves = Vec::new();
+
+            self.gen_moves(&mut moves);
+            return moves.is_empty();
+        }
+
+        false
+    }
+
     pub fn gen_moves(&self, out: &mut Vec<Board>) {
-        self.board.gen_moves(self.player, self.prev, self.tables, out)
+        self.board.gen_moves_with(
+            self.player, self.prev, self.tables, &self.rules, out)
     }
 
     pub fn gen_takes(&self, out: &mut Vec<Board>) {
@@ -198,20 +394,45 @@ impl<'a> Position<'a> {
 
     pub fn gen_piece_moves(&self, sq: usize) -> u32 {
         if (sq >= 16) == self.player {
-            self.board.gen_piece_moves(self.prev, self.tables, sq)
+            self.board.gen_piece_moves(self.prev, self.tables, &self.rules, sq)
         } else {
             0
         }
     }
 
     pub fn get_move(&self) -> (usize, usize) {
-        self.prev.get_move(&self.tables, &self.board)
+        self.prev.get_move(self.tables, &self.board)
+    }
+
+    /// Counts the positions reached after `depth` moves, not moving on from
+    /// positions where the game has ended.
+    pub fn perft(&mut self, depth: usize) -> usize {
+        if depth == 0 || self.game_end() {
+            return 1;
+        }
+
+        let mut moves = Vec::new();
+        let mut out = 0;
+
+        self.gen_moves(&mut moves);
+
+        if depth == 1 {
+            return moves.len();
+        }
+
+        for m in moves {
+            let u = self.do_move(m);
+            out += self.perft(depth - 1);
+            self.undo_move(u);
+        }
+
+        out
     }
 }
 
 use std::fmt;
 
-impl fmt::Display for Position<'_> {
+impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "p{} {}", self.player as usize + 1, self.score)?;
         writeln!(f)?;
@@ -224,15 +445,171 @@ impl fmt::Display for Position<'_> {
 
 #[allow(unused_imports)]
 mod tests {
-    extern crate test;
-    use test::Bencher;
     use super::*;
 
-    #[bench]
-    fn b_eval(b: &mut Bencher) {
-        let tables = Tables::new();
-        let pos = Position::new(&tables);
-        
-        b.iter(|| test::black_box(&pos).eval());
+    #[test]
+    fn t_perft() {
+        let tables = &TABLES;
+        let mut pos = Position::new(tables);
+        let mut moves = Vec::new();
+
+        pos.gen_moves(&mut moves);
+
+        assert_eq!(pos.perft(0), 1);
+        assert_eq!(pos.perft(1), moves.len());
+
+        let mut total = 0;
+
+        for m in moves {
+            let u = pos.do_move(m);
+            total += pos.perft(2);
+            pos.undo_move(u);
+        }
+
+        assert_eq!(pos.perft(3), total);
+        assert_eq!(pos.board, Board::new());
+    }
+
+    #[test]
+    fn t_rules() {
+        let tables = &TABLES;
+        let mut moves = Vec::new();
+
+        // two pawns that can merge into a drone
+        let mut pos = Position::new(tables);
+        pos.board = Board::from_desc("3q/4/4/4/2p1/1p2/4/4");
+
+        pos.gen_moves(&mut moves);
+        assert!(moves.iter().any(|m| m.drones() != 0));
+
+        pos.set_rules(RuleSet {field_promotion: false, ..RuleSet::STANDARD});
+        pos.gen_moves(&mut moves);
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|m| m.drones() == 0));
+
+        // a pawn crossing the canal, which may only go back without the
+        // no-reversal rule
+        let mut pos = Position::new(tables);
+        pos.board = Board::from_desc("3q/4/4/4/2p1/4/4/p3");
+
+        let start = pos.board;
+        pos.do_move(start.do_move(13, 16));
+
+        pos.gen_moves(&mut moves);
+        assert!(!moves.contains(&start));
+
+        pos.set_rules(RuleSet {no_reversal: false, ..RuleSet::STANDARD});
+        pos.gen_moves(&mut moves);
+        assert!(moves.contains(&start));
+
+        // a lead that ends the game
+        let mut pos = Position::from_fen("ec800137ca600653 0000000000000000 1 4", tables);
+
+        assert!(!pos.game_end());
+
+        pos.set_rules(RuleSet {winning_lead: Some(5), ..RuleSet::STANDARD});
+        assert!(!pos.game_end());
+
+        pos.set_rules(RuleSet {winning_lead: Some(4), ..RuleSet::STANDARD});
+        assert!(pos.game_end());
+        assert_eq!(pos.perft(2), 1);
+
+        pos.set_rules(RuleSet {end_without_moves: true, ..RuleSet::STANDARD});
+        assert!(!pos.game_end());
+    }
+
+    #[test]
+    fn t_move_limits() {
+        let tables = &TABLES;
+        let mut pos = Position::new(tables);
+        let mut moves = Vec::new();
+
+        // the counters survive a round trip, and older fens start them at 0
+        let fen = "ec800137ca600653 0000000000000000 1 -1 9 30";
+
+        assert_eq!(Position::from_fen(fen, tables).to_fen(), fen);
+        assert_eq!(Position::parse_fen(fen, tables).unwrap().get_quiet_moves(), 9);
+        assert!(Position::parse_fen(&fen[..fen.len() - 3], tables).is_none());
+
+        let old = Position::parse_fen("ec800137ca600653 0000000000000000 1 0", tables).unwrap();
+
+        assert_eq!((old.get_quiet_moves(), old.get_move_count()), (0, 0));
+
+        // a capture resets the quiet moves, and taking moves back restores
+        // both counters
+        let mut undos = Vec::new();
+
+        while pos.get_move_count() < 40 && !pos.game_end() {
+            pos.gen_takes(&mut moves);
+
+            if moves.is_empty() {
+                pos.gen_moves(&mut moves);
+            }
+
+            let quiet = pos.get_quiet_moves();
+            let captured = pos.board.pieces_value() > moves[0].pieces_value();
+
+            undos.push((pos.do_move(moves[0]), quiet));
+
+            assert_eq!(pos.get_quiet_moves(), if captured {0} else {quiet + 1});
+        }
+
+        while let Some((undo, quiet)) = undos.pop() {
+            pos.undo_move(undo);
+
+            assert_eq!(pos.get_quiet_moves(), quiet);
+            assert_eq!(pos.get_move_count() as usize, undos.len());
+        }
+
+        assert_eq!(pos.board, Board::new());
+
+        // either limit ends the game when reached
+        let mut pos = Position::from_fen(fen, tables);
+
+        pos.set_rules(RuleSet {no_capture_limit: Some(10), ..RuleSet::STANDARD});
+        assert_eq!(pos.moves_left(), Some(1));
+        assert!(!pos.game_end());
+
+        pos.set_rules(RuleSet {move_limit: Some(30), ..*pos.get_rules()});
+        assert_eq!(pos.moves_left(), Some(0));
+        assert!(pos.game_end());
+    }
+
+    #[test]
+    fn t_repetitions() {
+        let tables = &TABLES;
+        let mut pos = Position::new(tables);
+        let start = pos.key();
+
+        pos.set_rules(RuleSet {repetition_draw: true, ..RuleSet::STANDARD});
+
+        // each player moves a drone aside and back
+        let shuffle = [(2, 3), (29, 28), (3, 2), (28, 29)];
+        let mut undos = Vec::new();
+
+        for i in 0..8 {
+            let (sq1, sq2) = shuffle[i % 4];
+            let mov = pos.board.do_move(sq1, sq2);
+
+            assert!(!pos.game_end());
+            undos.push(pos.do_move(mov));
+        }
+
+        assert_eq!(pos.key(), start);
+        assert_eq!(pos.repetitions(), 2);
+        assert!(pos.is_repetition_draw());
+        assert!(pos.game_end());
+
+        pos.undo_move(undos.pop().unwrap());
+
+        assert_ne!(pos.key(), start);
+        assert_eq!(pos.repetitions(), 1);
+        assert!(!pos.game_end());
+
+        // the same board with the other player to move is another position
+        let mut other = Position::new(tables);
+        other.player = true;
+
+        assert_ne!(other.key(), start);
     }
 }
@@ -0,0 +1,343 @@
+use crate::gen_tables::*;
+use crate::board::*;
+use crate::position::*;
+
+use std::fmt;
+
+/// How `render` draws a board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderOptions {
+    /// draw the board from player 2's side
+    pub flipped: bool,
+    /// color each half of the board and the canal with ANSI escapes
+    pub color: bool,
+    /// the squares of a move to highlight
+    pub last_move: Option<(usize, usize)>,
+    /// squares to mark, such as the destinations of a piece
+    pub marks: u32,
+}
+
+const RESET: &str = "\x1b[0m";
+const CANAL: &str = "\x1b[1;34m";
+const HALF1: &str = "\x1b[30;48;5;223m";
+const HALF2: &str = "\x1b[30;48;5;152m";
+const LAST_MOVE: &str = "\x1b[30;48;5;221m";
+const MARK: &str = "\x1b[30;48;5;114m";
+
+fn glyph(board: &Board, sq: usize) -> char {
+    match (board.0 >> sq & 1, board.0 >> (sq + 32) & 1) {
+        (1, 1) => 'q',
+        (0, 1) => 'd',
+        (1, 0) => 'p',
+        _ => '.',
+    }
+}
+
+/// Draws `board` for the terminal with ranks down the left and files along
+/// the bottom, named as in move notation, and the canal between the two
+/// halves. Pieces use the letters of `Board::to_desc`. Without color, the
+/// squares of the last move are shown in brackets and marked squares in
+/// parentheses.
+pub fn render(board: &Board, options: &RenderOptions) -> String {
+    let ranks: Vec<usize> =
+        if options.flipped {(0..8).collect()} else {(0..8).rev().collect()};
+    let files: Vec<usize> =
+        if options.flipped {(0..4).collect()} else {(0..4).rev().collect()};
+
+    let last_move = options.last_move.map_or(0u32, |(from, to)| {
+        [from, to].iter().filter(|&&sq| sq < 32).fold(0, |m, &sq| m | 1 << sq)
+    });
+
+    let mut out = String::new();
+
+    for (i, &y) in ranks.iter().enumerate() {
+        if i == 4 {
+            let canal = "~".repeat(files.len() * 3);
+
+            if options.color {
+                out += &format!("   {}{}{}\n", CANAL, canal, RESET);
+            } else {
+                out += &format!("   {}\n", canal);
+            }
+        }
+
+        out += &format!("{}  ", y + 1);
+
+        for &x in files.iter() {
+            let sq = x + y * 4;
+            let c = glyph(board, sq);
+            let last = last_move & 1 << sq != 0;
+            let marked = options.marks & 1 << sq != 0;
+
+            if options.color {
+                let style = if last {
+                    LAST_MOVE
+                } else if marked {
+                    MARK
+                } else if sq < 16 {
+                    HALF1
+                } else {
+                    HALF2
+                };
+
+                out += &format!("{} {} ", style, c);
+            } else if last {
+                out += &format!("[{}]", c);
+            } else if marked {
+                out += &format!("({})", c);
+            } else {
+                out += &format!(" {} ", c);
+            }
+        }
+
+        if options.color {
+            out += RESET;
+        }
+        out += "\n";
+    }
+
+    out += "\n   ";
+
+    for &x in files.iter() {
+        // file letters as in stringify_move
+        out += &format!(" {} ", "dcba".as_bytes()[x] as char);
+    }
+
+    out += "\n";
+    out
+}
+
+/// Why `parse_diagram` rejected a diagram, with the line at fault counting
+/// from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagramError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for DiagramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for DiagramError {}
+
+fn is_footer(words: &[&str]) -> bool {
+    let mut files = words.iter().map(|w| w.to_lowercase()).collect::<Vec<_>>();
+
+    files.sort();
+    files == ["a", "b", "c", "d"]
+}
+
+/// Reads a board drawn by `render` or by the older `^ * A _` diagrams, with
+/// or without rank labels, the canal and the file footer. Rows go from rank
+/// 8 down to rank 1 unless the labels or the footer show the board was drawn
+/// from player 2's side. Highlighting brackets are ignored.
+pub fn parse_diagram(s: &str) -> Result<Board, DiagramError> {
+    let mut rows = Vec::new();
+    let mut flipped = None;
+    let mut last = 0;
+
+    for (i, line) in s.lines().enumerate() {
+        let line_no = i + 1;
+        let err = |msg: String| DiagramError {line: line_no, msg};
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.chars().all(|c| "~-=".contains(c)) {
+            continue;
+        }
+
+        let words = trimmed.split_whitespace().collect::<Vec<_>>();
+
+        last = line_no;
+
+        if is_footer(&words) {
+            flipped = flipped.or(Some(words[0].eq_ignore_ascii_case("d")));
+            continue;
+        }
+
+        let mut cells = trimmed;
+        let mut label = None;
+
+        if let Some(rank) = words[0].parse::<usize>().ok().filter(|r| (1..=8).contains(r)) {
+            label = Some(rank);
+            cells = trimmed[words[0].len()..].trim_start();
+        }
+
+        let mut row = Vec::new();
+
+        for c in cells.chars().filter(|c| !c.is_whitespace() && !"[]()".contains(*c)) {
+            row.push(match c {
+                '_' | '.' => 0,
+                '^' | 'p' | 'P' => 1,
+                '*' | 'd' | 'D' => 2,
+                'A' | 'q' | 'Q' => 3,
+                _ => return Err(err(format!("unknown piece '{}'", c))),
+            });
+        }
+
+        if row.len() != 4 {
+            return Err(err(format!("expected 4 squares, found {}", row.len())));
+        }
+        if rows.len() >= 8 {
+            return Err(err("more than 8 ranks".to_string()));
+        }
+        if rows.is_empty() {
+            if let Some(rank) = label {
+                flipped = Some(rank == 1);
+            }
+        }
+
+        rows.push((line_no, label, row));
+    }
+
+    if rows.len() != 8 {
+        return Err(DiagramError {
+            line: last.max(1),
+            msg: format!("expected 8 ranks, found {}", rows.len())
+        });
+    }
+
+    let flipped = flipped.unwrap_or(false);
+    let mut out = 0u64;
+
+    for (i, (line, label, row)) in rows.into_iter().enumerate() {
+        let y = if flipped {i} else {7 - i};
+
+        if label.is_some_and(|l| l != y + 1) {
+            return Err(DiagramError {
+                line,
+                msg: format!("expected rank {}, found rank {}", y + 1, label.unwrap())
+            });
+        }
+
+        for (j, piece) in row.into_iter().enumerate() {
+            let x = if flipped {j} else {3 - j};
+            let sq = x + y * 4;
+
+            out |= (piece & 1) << sq | (piece >> 1) << (sq + 32);
+        }
+    }
+
+    Ok(Board(out))
+}
+
+impl Board {
+    /// See `parse_diagram`.
+    pub fn from_diagram(s: &str) -> Result<Self, DiagramError> {
+        parse_diagram(s)
+    }
+}
+
+impl Position {
+    /// A position for analysis from a diagram read by `parse_diagram`, with
+    /// `player` to move (false for player 1) and no points captured yet.
+    pub fn from_diagram(s: &str, player: bool, tables: &'static Tables)
+        -> Result<Self, DiagramError>
+    {
+        let board = parse_diagram(s)?;
+        let fen = format!("{:016x} 0 {} 0", board.0, player as usize + 1);
+
+        Ok(Position::from_fen(&fen, tables))
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::gen_tables::*;
+    use super::*;
+
+    #[test]
+    fn t_render() {
+        let board = Board::new();
+        let plain = render(&board, &RenderOptions::default());
+        let trimmed = plain.lines().map(|l| l.trim_end()).collect::<Vec<_>>();
+
+        assert_eq!(trimmed.join("\n") + "\n", "\
+8   q  q  d  .
+7   q  d  p  .
+6   d  p  p  .
+5   .  .  .  .
+   ~~~~~~~~~~~~
+4   .  .  .  .
+3   .  p  p  d
+2   .  p  d  q
+1   .  d  q  q
+
+    a  b  c  d
+");
+        assert_eq!(board.to_string(), plain);
+
+        let flipped = render(&board, &RenderOptions {flipped: true, ..Default::default()});
+        let lines = flipped.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "1   q  q  d  . ");
+        assert_eq!(lines[10], "    d  c  b  a ");
+
+        // the squares named by a move are the ones highlighted
+        let (from, to) = parse_move("c3b4").unwrap();
+        let options = RenderOptions {
+            last_move: Some((from, to)),
+            marks: 1 << parse_square("a5").unwrap(),
+            ..Default::default()
+        };
+        let lines = render(&board, &options).lines().map(String::from).collect::<Vec<_>>();
+
+        assert_eq!(lines[3], "5  (.) .  .  . ");
+        assert_eq!(lines[5], "4   . [.] .  . ");
+        assert_eq!(lines[6], "3   .  p [p] d ");
+
+        let color = render(&board, &RenderOptions {color: true, ..options});
+
+        assert!(color.contains(HALF1) && color.contains(HALF2));
+        assert!(color.contains(LAST_MOVE) && color.contains(MARK));
+        assert!(color.contains(CANAL));
+    }
+
+    #[test]
+    fn t_parse_diagram() {
+        let board = Board::from_desc("qd2/1p1p/d3/2p1/1q2/p3/1dp1/2dq");
+
+        let options = [
+            RenderOptions::default(),
+            RenderOptions {flipped: true, ..Default::default()},
+            RenderOptions {last_move: Some((5, 9)), marks: 0xf0, ..Default::default()},
+        ];
+
+        for options in options.iter() {
+            assert_eq!(parse_diagram(&render(&board, options)), Ok(board));
+        }
+
+        // the old Display format, without labels or footer
+        let old = "\
+A A * _
+A * ^ _
+* ^ ^ _
+_ _ _ _
+_ _ _ _
+_ ^ ^ *
+_ ^ * A
+_ * A A
+";
+        assert_eq!(parse_diagram(old), Ok(Board::new()));
+
+        let labelled = "8  A A * _\n7  A * ^ _\n6  * ^ ^ _\n5  _ _ _ _\n\
+                        4  _ _ _ _\n3  _ ^ ^ *\n2  _ ^ * A\n1  _ * A A\n\n   A B C D\n";
+        assert_eq!(parse_diagram(labelled), Ok(Board::new()));
+
+        let err = |s: &str| parse_diagram(s).unwrap_err();
+
+        assert_eq!(err("A A * _\nA * x _").line, 2);
+        assert!(err("A A * _\nA * x _").msg.contains("unknown piece 'x'"));
+        assert_eq!(err("A A *\n").msg, "expected 4 squares, found 3");
+        assert_eq!(err(&old[..old.len() - 8]).msg, "expected 8 ranks, found 7");
+        assert_eq!(err(&labelled.replace("7  A", "6  A")).line, 2);
+
+        let tables = &TABLES;
+        let pos = Position::from_diagram(old, true, tables).unwrap();
+
+        assert_eq!(pos.board, Board::new());
+        assert!(pos.get_player());
+    }
+}
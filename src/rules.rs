@@ -0,0 +1,104 @@
+/// Which of the optional rules a game is played with. How the pieces move
+/// is up to the `Variant` the tables were built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSet {
+    /// whether a player without drones may merge two pawns into one, and
+    /// a player without queens a pawn and a drone
+    pub field_promotion: bool,
+    /// whether a move may not put back the piece the opponent just moved
+    /// across the canal
+    pub no_reversal: bool,
+    /// whether the game also ends when the player to move has no legal
+    /// moves, rather than only when a half of the board is empty
+    pub end_without_moves: bool,
+    /// a lead in points that ends the game
+    pub winning_lead: Option<i32>,
+    /// moves in a row without a capture that end the game, counting the
+    /// moves of both players
+    pub no_capture_limit: Option<u32>,
+    /// moves by both players together that end the game
+    pub move_limit: Option<u32>,
+    /// whether the third occurrence of a position ends the game in a draw
+    pub repetition_draw: bool,
+}
+
+impl RuleSet {
+    pub const STANDARD: Self = Self {
+        field_promotion: true,
+        no_reversal: true,
+        end_without_moves: false,
+        winning_lead: None,
+        no_capture_limit: None,
+        move_limit: None,
+        repetition_draw: false,
+    };
+
+    pub fn standard() -> Self {
+        Self::STANDARD
+    }
+
+    /// Sets the rule called `name`, the name of one of the fields, from
+    /// `value`: `true` or `false` for a rule that is on or off, and a
+    /// number for a limit, with `0` or `none` for no limit.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let bad = || format!("bad value for {}: {}", name, value);
+        let flag = || value.parse::<bool>().map_err(|_| bad());
+        let limit = || match value {
+            "none" | "0" => Ok(None),
+            _ => value.parse::<u32>().map(Some).map_err(|_| bad()),
+        };
+
+        match name {
+            "field_promotion" => self.field_promotion = flag()?,
+            "no_reversal" => self.no_reversal = flag()?,
+            "end_without_moves" => self.end_without_moves = flag()?,
+            "winning_lead" => self.winning_lead = limit()?.map(|n| n as i32),
+            "no_capture_limit" => self.no_capture_limit = limit()?,
+            "move_limit" => self.move_limit = limit()?,
+            "repetition_draw" => self.repetition_draw = flag()?,
+            _ => return Err(format!("unknown rule: {}", name)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_set() {
+        let mut rules = RuleSet::STANDARD;
+
+        rules.set("field_promotion", "false").unwrap();
+        rules.set("end_without_moves", "true").unwrap();
+        rules.set("winning_lead", "10").unwrap();
+        rules.set("no_capture_limit", "40").unwrap();
+        rules.set("move_limit", "0").unwrap();
+        rules.set("repetition_draw", "true").unwrap();
+
+        assert_eq!(rules, RuleSet {
+            field_promotion: false,
+            end_without_moves: true,
+            winning_lead: Some(10),
+            no_capture_limit: Some(40),
+            repetition_draw: true,
+            ..RuleSet::STANDARD
+        });
+
+        rules.set("winning_lead", "none").unwrap();
+
+        assert_eq!(rules.winning_lead, None);
+
+        assert!(rules.set("no_reversal", "maybe").is_err());
+        assert!(rules.set("winning_lead", "-3").is_err());
+        assert!(rules.set("castling", "true").is_err());
+    }
+}
@@ -1,10 +1,15 @@
+use crate::gen_tables::*;
 use crate::board::*;
 use crate::position::*;
+use crate::eval::*;
+use crate::tablebase::*;
+use crate::book::*;
 
 use rand::seq::SliceRandom;
 
 use std::mem;
-use std::cmp::Ordering;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::*;
@@ -14,24 +19,289 @@ use wasm_timer::*;
 
 const TABLE_SIZE: usize = 1_048_573;
 
-pub struct Searcher<'a> {
-    pos: Position<'a>,
+/// Bytes used by one transposition table entry.
+pub const ENTRY_SIZE: usize = mem::size_of::<(Board, bool, usize, i32)>();
+
+pub const MAX_SKILL: usize = 10;
+
+/// Positions searched by `bench`.
+pub const BENCH_POSITIONS: [&str; 4] = [
+    "ec800137ca600653 0000000000000000 1 0",
+    "ec200a27ca406643 0000000000000000 2 3",
+    "c080404385024821 0000000000000000 1 0",
+    "a001400680c10304 0000000000000000 1 0",
+];
+
+/// Search limits for one skill level. `max_loss` is the largest score drop
+/// (in eval units, 100 per captured point) the engine will accept when it
+/// picks among the moves near the best one, and `rating` is a rough guide to
+/// the playing strength for display purposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Skill {
+    pub max_depth: usize,
+    pub max_nodes: usize,
+    pub max_loss: i32,
+    pub rating: i32,
+}
+
+const SKILL_LEVELS: [Skill; MAX_SKILL + 1] = [
+    Skill {max_depth: 1, max_nodes: 500,    max_loss: 300, rating: 400},
+    Skill {max_depth: 1, max_nodes: 1000,   max_loss: 250, rating: 550},
+    Skill {max_depth: 2, max_nodes: 2000,   max_loss: 200, rating: 700},
+    Skill {max_depth: 2, max_nodes: 4000,   max_loss: 160, rating: 850},
+    Skill {max_depth: 3, max_nodes: 8000,   max_loss: 120, rating: 1000},
+    Skill {max_depth: 3, max_nodes: 16000,  max_loss: 90,  rating: 1150},
+    Skill {max_depth: 4, max_nodes: 32000,  max_loss: 60,  rating: 1300},
+    Skill {max_depth: 5, max_nodes: 64000,  max_loss: 40,  rating: 1450},
+    Skill {max_depth: 6, max_nodes: 128000, max_loss: 25,  rating: 1600},
+    Skill {max_depth: 8, max_nodes: 512000, max_loss: 10,  rating: 1800},
+    Skill {
+        max_depth: usize::MAX,
+        max_nodes: usize::MAX,
+        max_loss: 0,
+        rating: 2000
+    },
+];
+
+impl Skill {
+    /// Limits for `level`, clamped to `0..=MAX_SKILL`.
+    pub fn level(level: usize) -> Self {
+        SKILL_LEVELS[level.min(MAX_SKILL)]
+    }
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Self::level(MAX_SKILL)
+    }
+}
+
+/// Budget for one search. The search stops at whichever limit it reaches
+/// first, including those of the searcher's skill level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// milliseconds
+    pub time: usize,
+    pub depth: usize,
+    pub nodes: usize,
+}
+
+impl Limits {
+    pub fn time(time: usize) -> Self {
+        Self {time, ..Self::default()}
+    }
+
+    pub fn depth(depth: usize) -> Self {
+        Self {depth, ..Self::default()}
+    }
+
+    pub fn nodes(nodes: usize) -> Self {
+        Self {nodes, ..Self::default()}
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            time: usize::MAX,
+            depth: usize::MAX,
+            nodes: usize::MAX,
+        }
+    }
+}
+
+/// Progress of a search, reported after each completed iteration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchInfo {
+    pub depth: usize,
+    pub score: i32,
+    pub nodes: usize,
+    /// milliseconds
+    pub time: usize,
+    /// the chosen move followed by the replies the transposition table
+    /// still remembers
+    pub pv: Vec<Board>,
+}
+
+pub struct Searcher {
+    pos: Position,
     moves: Vec<Vec<Board>>,
-    transposition: Vec<(Board, bool, usize, i32)>
+    transposition: Vec<(Board, bool, usize, i32)>,
+    params: EvalParams,
+    skill: Skill,
+    nodes: usize,
+    max_nodes: usize,
+    start: Instant,
+    time: u128,
+    depth: usize,
+    stop: Arc<AtomicBool>,
+    aborted: bool,
+    /// whether a root move has a score yet in this search
+    scored_move: bool,
+    /// how much worse than an even position a draw is for the side that
+    /// starts the search, in eval units
+    contempt: i32,
+    root_player: bool,
+    tablebase: Option<Arc<Tablebase>>,
+    book: Option<Arc<Book>>,
 }
 
-impl<'a> Searcher<'a> {
-    pub fn new(pos: Position<'a>) -> Self {
+impl Searcher {
+    pub fn new(pos: Position) -> Self {
         Self {
             pos,
             moves: Vec::new(),
-            transposition: vec![(Board::empty(), false, 0, 0); TABLE_SIZE]
+            transposition: vec![(Board::empty(), false, 0, 0); TABLE_SIZE],
+            params: EvalParams::default(),
+            skill: Skill::default(),
+            nodes: 0,
+            max_nodes: usize::MAX,
+            start: Instant::now(),
+            time: u128::MAX,
+            depth: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+            aborted: false,
+            scored_move: false,
+            contempt: 0,
+            root_player: false,
+            tablebase: None,
+            book: None,
+        }
+    }
+
+    /// Resizes the transposition table to hold `entries` entries, clearing
+    /// it.
+    pub fn set_hash_entries(&mut self, entries: usize) {
+        self.transposition = vec![(Board::empty(), false, 0, 0); entries.max(1)];
+    }
+
+    /// Resizes the transposition table to use about `mb` megabytes.
+    pub fn set_hash_size(&mut self, mb: usize) {
+        self.set_hash_entries(mb * 1024 * 1024 / ENTRY_SIZE);
+    }
+
+    fn table_index(&self, board: &Board) -> usize {
+        (board.0 % self.transposition.len() as u64) as usize
+    }
+
+    pub fn set_skill(&mut self, level: usize) {
+        self.skill = Skill::level(level);
+    }
+
+    pub fn get_skill(&self) -> Skill {
+        self.skill
+    }
+
+    pub fn get_nodes(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn set_params(&mut self, params: EvalParams) {
+        self.params = params;
+    }
+
+    pub fn get_params(&self) -> &EvalParams {
+        &self.params
+    }
+
+    /// Replaces the flag that makes a running search return early once set,
+    /// allowing another thread to stop it.
+    pub fn set_stop(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    pub fn get_stop(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Sets how much the engine dislikes draws by repetition. Positive
+    /// values make it avoid them unless it is behind by at least as much,
+    /// negative values make it seek them.
+    pub fn set_contempt(&mut self, contempt: i32) {
+        self.contempt = contempt;
+    }
+
+    pub fn get_contempt(&self) -> i32 {
+        self.contempt
+    }
+
+    /// Sets the endgame tables to play from once few enough pieces are
+    /// left.
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    pub fn get_tablebase(&self) -> Option<Arc<Tablebase>> {
+        self.tablebase.clone()
+    }
+
+    /// Sets the opening book to play from while it has moves for the
+    /// position searched.
+    pub fn set_book(&mut self, book: Option<Arc<Book>>) {
+        self.book = book;
+    }
+
+    pub fn get_book(&self) -> Option<Arc<Book>> {
+        self.book.clone()
+    }
+
+    /// Whether the search should stop. Nothing stops it before some root
+    /// move has a score, so that a move is found.
+    fn out_of_limits(&self) -> bool {
+        self.scored_move && (
+            self.start.elapsed().as_millis() >= self.time ||
+            self.nodes >= self.max_nodes ||
+            self.stop.load(Ordering::Relaxed)
+        )
+    }
+
+    fn eval(&self) -> i32 {
+        self.params.eval(&self.pos)
+    }
+
+    /// The score of a draw for the side to move.
+    fn draw_score(&self) -> i32 {
+        if self.pos.get_player() == self.root_player {
+            -self.contempt
+        } else {
+            self.contempt
         }
     }
 
-    fn sort_moves(&self, moves: &mut Vec<Board>) {
+    /// Evaluation of a finished game. One that ran into a move limit is
+    /// decided on points alone, whatever is left on the board.
+    fn end_eval(&self) -> i32 {
+        if self.pos.is_repetition_draw() {
+            self.draw_score()
+        } else if self.pos.moves_left() == Some(0) && !self.pos.board.game_end() {
+            let score = self.pos.get_score();
+            let score = if self.pos.get_player() {-score} else {score};
+
+            self.params.captured * score * self.params.end_scale
+        } else {
+            self.eval() * self.params.end_scale
+        }
+    }
+
+    /// The final result of the game, scored like a finished one, if the
+    /// position is in the tables.
+    fn tablebase_eval(&self) -> Option<i32> {
+        let value = self.tablebase.as_ref()?.probe(&self.pos)?;
+        let score = self.pos.get_score();
+        let score = if self.pos.get_player() {-score} else {score};
+
+        Some(self.params.captured * (score + value) * self.params.end_scale)
+    }
+
+    /// Whether the no-capture limit or the move limit could end the game
+    /// within `depth` moves.
+    fn limit_in_reach(&self, depth: usize) -> bool {
+        self.pos.moves_left().is_some_and(|n| n as usize <= depth)
+    }
+
+    fn sort_moves(&self, moves: &mut [Board]) {
         moves.sort_by_cached_key(|board| {
-            let ind = (board.0 % TABLE_SIZE as u64) as usize;
+            let ind = self.table_index(board);
             let (b, play, _, score) = self.transposition[ind];
 
             let board_eq = b == *board && self.pos.get_player() != play;
@@ -44,9 +314,21 @@ impl<'a> Searcher<'a> {
         });
     }
 
-    fn quiesce(&mut self, mut alpha: i32, beta: i32) -> i32 {
-        if self.pos.board.game_end() {
-            return self.pos.eval() * 100;
+    pub fn quiesce(&mut self, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(1024) && self.out_of_limits() {
+            self.aborted = true;
+        }
+        if self.aborted {
+            return 0;
+        }
+
+        if self.pos.game_end() {
+            return self.end_eval();
+        }
+        if let Some(score) = self.tablebase_eval() {
+            return score;
         }
 
         let mut moves = Vec::new();
@@ -54,11 +336,11 @@ impl<'a> Searcher<'a> {
         self.pos.gen_takes(&mut moves);
 
         if moves.is_empty() {
-            return self.pos.eval();
+            return self.eval();
         }
 
         // a player that gains nothing from taking won't take
-        let null_score = self.pos.eval();
+        let null_score = self.eval();
 
         if null_score >= beta {
             return beta + 1;
@@ -72,6 +354,9 @@ impl<'a> Searcher<'a> {
             let score = -self.quiesce(-beta, -alpha);
             self.pos.undo_move(u);
 
+            if self.aborted {
+                return 0;
+            }
             if score >= beta {
                 return beta + 1;
             }
@@ -84,21 +369,42 @@ impl<'a> Searcher<'a> {
     }
 
     fn alphabeta(&mut self, mut alpha: i32, beta: i32, depth: usize) -> i32 {
-        if self.pos.board.game_end() {
-            return self.pos.eval() * 100;
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(1024) && self.out_of_limits() {
+            self.aborted = true;
+        }
+        if self.aborted {
+            return 0;
+        }
+
+        // going back to a position already seen leaves the players to
+        // repeat themselves, so it counts as a draw whatever the rules
+        if self.pos.repetitions() > 0 {
+            return self.draw_score();
+        }
+
+        if self.pos.game_end() {
+            return self.end_eval();
+        }
+        if let Some(score) = self.tablebase_eval() {
+            return score;
         }
 
         if depth == 0 {
             return self.quiesce(alpha, beta);
         }
 
-        let ind = (self.pos.board.0 % TABLE_SIZE as u64) as usize;
+        let ind = self.table_index(&self.pos.board);
         let (board, play, depth2, score) = self.transposition[ind];
 
-        let board_eq = self.pos.board == board && self.pos.get_player() == play;
+        // the table doesn't know the move counters, so its scores can't be
+        // trusted once a move limit is within reach
+        let board_eq = self.pos.board == board && self.pos.get_player() == play &&
+            !self.limit_in_reach(depth);
 
         if depth2 >= depth && board_eq {
-            return self.pos.eval() + score;
+            return self.eval() + score;
         }
 
         let mut moves = mem::take(&mut self.moves[depth]);
@@ -111,6 +417,9 @@ impl<'a> Searcher<'a> {
             let score = -self.alphabeta(-beta, -alpha, depth - 1);
             self.pos.undo_move(u);
 
+            if self.aborted {
+                break;
+            }
             if score >= beta {
                 moves.clear();
                 self.moves[depth] = moves;
@@ -124,57 +433,74 @@ impl<'a> Searcher<'a> {
         moves.clear();
         self.moves[depth] = moves;
 
-        if depth > depth2 || !board_eq {
-            self.transposition[ind] = (self.pos.board, self.pos.get_player(), depth, alpha - self.pos.eval())
+        if self.aborted {
+            return 0;
+        }
+
+        if (depth > depth2 || !board_eq) && !self.limit_in_reach(depth) {
+            self.transposition[ind] = (self.pos.board, self.pos.get_player(), depth, alpha - self.eval())
         }
 
         alpha
     }
 
-    fn best_moves(&mut self, depth: usize, now: Instant, time: u128)
-        -> (Vec<Board>, i32)
-    {
-        if self.pos.board.game_end() {
-            return (Vec::new(), self.pos.eval() * 100);
+    fn best_moves(&mut self, depth: usize) -> (Vec<Board>, i32) {
+        if self.pos.game_end() {
+            return (Vec::new(), self.end_eval());
         }
 
-        let ind = (self.pos.board.0 % TABLE_SIZE as u64) as usize;
+        let ind = self.table_index(&self.pos.board);
         let (board, play, depth2, _) = self.transposition[ind];
 
         let board_eq = self.pos.board == board && self.pos.get_player() == play;
 
-        let mut best_moves = Vec::new();
+        let mut scored = Vec::new();
         let mut best_score = -1000000;
         let mut moves = mem::take(&mut self.moves[depth]);
 
         self.pos.gen_moves(&mut moves);
         self.sort_moves(&mut moves);
 
+        // moves within max_loss of the best get exact scores so that a
+        // reduced skill level can choose among them
+        let margin = self.skill.max_loss;
+
         for m in moves.iter().rev() {
-            if now.elapsed().as_millis() >= time {
-                return (Vec::new(), 0);
+            if self.out_of_limits() {
+                self.aborted = true;
+                break;
             }
 
             let u = self.pos.do_move(*m);
-            let score = -self.alphabeta(-1000000, -best_score, depth - 1);
+            let score = -self.alphabeta(-1000000, margin - best_score, depth - 1);
 
             self.pos.undo_move(u);
 
-            match score.cmp(&best_score) {
-                Ordering::Greater => {
-                    best_moves.clear();
-                    best_moves.push(*m);
-                    best_score = score;
-                }
-                Ordering::Equal => {
-                    best_moves.push(*m);
-                }
-                _ => {},
+            if self.aborted {
+                break;
             }
+
+            best_score = best_score.max(score);
+            scored.push((*m, score));
+            self.scored_move = true;
         }
 
-        if depth > depth2 || !board_eq {
-            self.transposition[ind] = (self.pos.board, self.pos.get_player(), depth, best_score - self.pos.eval())
+        // an unfinished iteration is thrown away, except the first, which
+        // chooses among the moves it has scored
+        if self.aborted && (depth > 1 || scored.is_empty()) {
+            moves.clear();
+            self.moves[depth] = moves;
+            return (Vec::new(), 0);
+        }
+
+        let best_moves = scored
+            .into_iter()
+            .filter(|(_, score)| *score >= best_score - margin)
+            .map(|(m, _)| m)
+            .collect();
+
+        if (depth > depth2 || !board_eq) && !self.aborted && !self.limit_in_reach(depth) {
+            self.transposition[ind] = (self.pos.board, self.pos.get_player(), depth, best_score - self.eval())
         }
 
         moves.clear();
@@ -183,19 +509,161 @@ impl<'a> Searcher<'a> {
         (best_moves, best_score)
     }
 
-    pub fn into_position(self) -> Position<'a> {
+    pub fn get_position(&self) -> &Position {
+        &self.pos
+    }
+
+    pub fn into_position(self) -> Position {
         self.pos
     }
 
+    /// Replaces the position to search, keeping the transposition table.
+    pub fn set_position(&mut self, pos: Position) {
+        self.pos = pos;
+    }
+
+    /// Plays out the captures `quiesce` prefers over standing pat until the
+    /// side to move would rather not capture, and returns that position.
+    pub fn quiet_position(&mut self) -> Position {
+        let start = self.pos.clone();
+        let mut moves = Vec::new();
+
+        // no limits apply outside a search
+        self.aborted = false;
+        self.scored_move = false;
+
+        while !self.pos.game_end() {
+            self.pos.gen_takes(&mut moves);
+
+            let mut best_score = self.eval();
+            let mut best_move = None;
+
+            for m in moves.iter() {
+                let u = self.pos.do_move(*m);
+                let score = -self.quiesce(-1000000, 1000000);
+                self.pos.undo_move(u);
+
+                if score > best_score {
+                    best_score = score;
+                    best_move = Some(*m);
+                }
+            }
+
+            match best_move {
+                Some(m) => {self.pos.do_move(m);}
+                None => break
+            }
+        }
+
+        mem::replace(&mut self.pos, start)
+    }
+
     pub fn ab_search(&mut self, time: usize) -> (Option<Board>, i32) {
-        let time = time as u128;
-        let now = Instant::now();
+        self.search(&Limits::time(time))
+    }
+
+    /// Follows `first` with the reply the transposition table rates best
+    /// for each side in turn, for up to `depth` moves.
+    fn principal_variation(&mut self, first: Board, depth: usize) -> Vec<Board> {
+        let start = self.pos.clone();
+        let mut moves = Vec::new();
+        let mut pv = vec![first];
+
+        self.pos.do_move(first);
+
+        while pv.len() < depth && !self.pos.game_end() {
+            self.pos.gen_moves(&mut moves);
+
+            let mut best = None;
+
+            for m in moves.iter() {
+                let (board, play, _, score) = self.transposition[self.table_index(m)];
+
+                if board != *m || play == self.pos.get_player() {
+                    continue;
+                }
+
+                let u = self.pos.do_move(*m);
+                let score = self.eval() + score;
+                self.pos.undo_move(u);
+
+                if best.is_none_or(|(_, s)| score < s) {
+                    best = Some((*m, score));
+                }
+            }
+
+            match best {
+                Some((m, _)) => {
+                    self.pos.do_move(m);
+                    pv.push(m);
+                }
+                None => break
+            }
+        }
+
+        self.pos = start;
+        pv
+    }
+
+    /// Iterative deepening search within `limits`, returning the chosen move
+    /// and its score, or `None` if there are no legal moves.
+    pub fn search(&mut self, limits: &Limits) -> (Option<Board>, i32) {
+        self.search_with_info(limits, |_| {})
+    }
 
-        let mut best = Vec::new();
+    /// Like `search`, calling `info` after each completed iteration.
+    pub fn search_with_info<F>(&mut self, limits: &Limits, mut info: F)
+        -> (Option<Board>, i32)
+        where F: FnMut(&SearchInfo)
+    {
+        let max_depth = limits.depth.min(self.skill.max_depth);
+
+        let mut best = None;
         let mut score = 0;
-        let mut d = 1;
 
-        while now.elapsed().as_millis() < time {
+        self.start = Instant::now();
+        self.time = limits.time as u128;
+        self.nodes = 0;
+        self.max_nodes = limits.nodes.min(self.skill.max_nodes);
+        self.aborted = false;
+        self.scored_move = false;
+        self.depth = 1;
+        self.root_player = self.pos.get_player();
+
+        if let Some(mov) = self.book.as_ref().and_then(|book| book.probe(&self.pos)) {
+            let score = self.eval();
+
+            info(&SearchInfo {
+                depth: 0,
+                score,
+                nodes: self.nodes,
+                time: self.start.elapsed().as_millis() as usize,
+                pv: vec![mov],
+            });
+
+            return (Some(mov), score);
+        }
+
+        // within the tables the best move is known without searching
+        if let Some(tablebase) = self.tablebase.clone() {
+            if let Some((mov, _)) = tablebase.best_move(&self.pos) {
+                let score = self.tablebase_eval().unwrap();
+
+                info(&SearchInfo {
+                    depth: 0,
+                    score,
+                    nodes: self.nodes,
+                    time: self.start.elapsed().as_millis() as usize,
+                    pv: vec![mov],
+                });
+
+                return (Some(mov), score);
+            }
+        }
+
+        while self.depth <= max_depth && !self.out_of_limits() {
+            let d = self.depth;
+
             for _ in 0..d + 1 {
                 if d >= self.moves.len() {
                     self.moves.push(Vec::new());
@@ -204,57 +672,290 @@ impl<'a> Searcher<'a> {
                 }
             }
 
-            let (m, s) = self.best_moves(d, now, time);
+            let (m, s) = self.best_moves(d);
 
             if m.is_empty() {
-                return (best.choose(&mut rand::thread_rng()).cloned(), score);
-            } else {
-                best = m;
-                score = s
+                break;
             }
-            d += 1;
-        }
 
-        if best.is_empty() {
-            (None, score)
-        } else {
-            (best.choose(&mut rand::thread_rng()).cloned(), score)
+            best = m.choose(&mut rand::thread_rng()).cloned();
+            score = s;
+
+            info(&SearchInfo {
+                depth: d,
+                score,
+                nodes: self.nodes,
+                time: self.start.elapsed().as_millis() as usize,
+                pv: self.principal_variation(best.unwrap(), d),
+            });
+
+            self.depth += 1;
         }
+
+        (best, score)
     }
 }
 
+/// Searches each of `BENCH_POSITIONS` to `depth` with a fresh searcher and
+/// returns the total number of nodes. Since nothing random affects the node
+/// count, it only changes when the search does.
+pub fn bench(tables: &'static Tables, depth: usize) -> usize {
+    BENCH_POSITIONS
+        .iter()
+        .map(|fen| {
+            let mut searcher = Searcher::new(Position::from_fen(fen, tables));
+
+            searcher.set_hash_size(16);
+            searcher.search(&Limits::depth(depth));
+            searcher.get_nodes()
+        })
+        .sum()
+}
+
 #[allow(unused_imports)]
 mod tests {
-    extern crate test;
-    use test::Bencher;
     use crate::gen_tables::*;
+    use crate::game::*;
+    use crate::rules::*;
     use super::*;
 
-    #[bench]
-    fn b_alphabeta(b: &mut Bencher) {
-        let tables = Tables::new();
-        let position = Position::new(&tables);
+    #[test]
+    fn t_skill_levels() {
+        for level in 1..=MAX_SKILL {
+            let weaker = Skill::level(level - 1);
+            let stronger = Skill::level(level);
 
-        b.iter(|| {
-            let mut searcher = Searcher::new(position.clone());
-            searcher.moves = vec![Vec::new(); 7];
-            searcher.alphabeta(-1000000, 1000000, 5)
-        });
+            assert!(weaker.max_depth <= stronger.max_depth);
+            assert!(weaker.max_nodes <= stronger.max_nodes);
+            assert!(weaker.max_loss >= stronger.max_loss);
+            assert!(weaker.rating < stronger.rating);
+        }
+
+        assert_eq!(Skill::level(MAX_SKILL + 5), Skill::default());
+
+        // on a crowded board the captures alone at depth 1 take far more
+        // nodes than level 0 allows
+        let tables = &TABLES;
+        let mut searcher = Searcher::new(Position::from_fen("5555555555555555 0 1 0", tables));
+
+        searcher.set_skill(0);
+
+        let (mov, _) = searcher.ab_search(10000);
+
+        // the limit is checked every 1024 nodes
+        assert!(mov.is_some());
+        assert!(searcher.get_nodes() < Skill::level(0).max_nodes + 1024);
     }
 
-    #[bench]
-    fn b_iterative_deepening(b: &mut Bencher) {
-        let tables = Tables::new();
-        let position = Position::new(&tables);
+    #[test]
+    fn t_quiet_position() {
+        let tables = &TABLES;
 
-        b.iter(|| {
-            let mut searcher = Searcher::new(position.clone());
-            searcher.moves = vec![Vec::new(); 7];
+        // player 1's pawn on c4 can take the drone on b5 for free
+        let board = Board::from_desc("q3/4/4/1d2/2p1/4/4/3q");
+        let pos = Position::from_fen(&format!("{:016x} 0 1 0", board.0), tables);
+        let mut searcher = Searcher::new(pos.clone());
 
-            for d in 0..5 {
-                searcher.alphabeta(-1000000, 1000000, d);
-            }
-            searcher.alphabeta(-1000000, 1000000, 5)
+        let quiet = searcher.quiet_position();
+
+        assert_eq!(quiet.get_score(), 2);
+        assert!(quiet.get_player());
+        assert_eq!(searcher.into_position().board, pos.board);
+
+        // nothing to take from the start position
+        let mut searcher = Searcher::new(Position::new(tables));
+
+        assert_eq!(searcher.quiet_position().board, Board::new());
+    }
+
+    #[test]
+    fn t_limits() {
+        let tables = &TABLES;
+        let mut searcher = Searcher::new(Position::new(tables));
+
+        searcher.set_hash_entries(1000);
+
+        let (mov, _) = searcher.search(&Limits::depth(2));
+
+        assert!(mov.is_some());
+
+        let (mov, _) = searcher.search(&Limits::nodes(2000));
+
+        // the limit is checked every 1024 nodes
+        assert!(mov.is_some());
+        assert!(searcher.get_nodes() < 2000 + 2048);
+    }
+
+    #[test]
+    fn t_move_limit() {
+        let tables = &TABLES;
+
+        let mut takes = Vec::new();
+
+        // a position where the player to move can capture, with that player
+        // a point behind one move before the no-capture limit, so that any
+        // move but a capture loses
+        let mut pos = BENCH_POSITIONS.iter()
+            .map(|fen| {
+                let words = fen.split(' ').collect::<Vec<_>>();
+                let score = if words[2] == "1" {-1} else {1};
+
+                Position::from_fen(&format!("{} {} {} {} 9 30", words[0], words[1], words[2], score), tables)
+            })
+            .find(|pos| {
+                pos.gen_takes(&mut takes);
+                !takes.is_empty()
+            })
+            .unwrap();
+
+        pos.set_rules(RuleSet {no_capture_limit: Some(10), ..RuleSet::STANDARD});
+
+        for depth in 1..4 {
+            let mut searcher = Searcher::new(pos.clone());
+
+            searcher.set_hash_entries(1000);
+
+            let (mov, _) = searcher.search(&Limits::depth(depth));
+
+            assert!(takes.contains(&mov.unwrap()));
+        }
+
+        // with nothing to capture the game is lost on points, which the
+        // search sees once the limit is in reach
+        let lost = -DEFAULT_PARAMS.captured * DEFAULT_PARAMS.end_scale;
+
+        for (limit, expected) in [(10, true), (20, false)].iter() {
+            let mut pos = Position::from_fen(
+                &format!("{:016x} 0 1 -1 9 30", Board::new().0), tables);
+
+            pos.set_rules(RuleSet {no_capture_limit: Some(*limit), ..RuleSet::STANDARD});
+            pos.gen_takes(&mut takes);
+            assert!(takes.is_empty());
+
+            let mut searcher = Searcher::new(pos);
+
+            searcher.set_hash_entries(1000);
+
+            let (_, score) = searcher.search(&Limits::depth(2));
+
+            assert_eq!(score == lost, *expected);
+        }
+    }
+
+    #[test]
+    fn t_contempt() {
+        let tables = &TABLES;
+        let mut pos = Position::new(tables);
+
+        // player 2 can go back to the start position, repeating it
+        for &(sq1, sq2) in [(2, 3), (29, 28), (3, 2)].iter() {
+            pos.do_move(pos.board.do_move(sq1, sq2));
+        }
+
+        let repeat = pos.board.do_move(28, 29);
+
+        for &(contempt, seek) in [(-1000, true), (1000, false)].iter() {
+            let mut searcher = Searcher::new(pos.clone());
+
+            searcher.set_hash_entries(1000);
+            searcher.set_contempt(contempt);
+
+            let (mov, _) = searcher.search(&Limits::depth(2));
+
+            assert_eq!(mov == Some(repeat), seek);
+        }
+    }
+
+    #[test]
+    fn t_tablebase() {
+        let tables = &TABLES;
+        let tablebase = Arc::new(Tablebase::generate(2));
+        let params = EvalParams::default();
+        let mut pos = Position::new(tables).with_rules(TABLEBASE_RULES);
+
+        // at the root the tables pick the move
+        pos.board = Board::from_desc("4/4/4/1q2/2p1/4/4/4");
+
+        let mut searcher = Searcher::new(pos.clone());
+
+        searcher.set_tablebase(Some(tablebase.clone()));
+
+        let (mov, score) = searcher.search(&Limits::depth(3));
+
+        assert_eq!(mov, Some(pos.board.do_move(13, 18)));
+        assert_eq!(score, params.captured * 3 * params.end_scale);
+        assert_eq!(searcher.get_nodes(), 0);
+
+        // taking the queen leaves a position in the tables
+        pos.board = Board::from_desc("4/4/4/1q2/2p1/4/4/3p");
+
+        let take = pos.board.do_move(13, 18);
+        let mut child = pos.clone();
+
+        child.do_move(take);
+
+        let rest = tablebase.probe(&child).unwrap();
+        let mut searcher = Searcher::new(pos);
+
+        searcher.set_hash_entries(1000);
+        searcher.set_tablebase(Some(tablebase));
+
+        let (_, score) = searcher.search(&Limits::depth(1));
+
+        assert!(score >= params.captured * (3 - rest) * params.end_scale);
+    }
+
+    #[test]
+    fn t_book() {
+        let tables = &TABLES;
+        let mut game = Game::new(Position::new(tables));
+        let mov = game.legal_moves()[3];
+        let mut book = Book::new();
+
+        game.do_move(mov);
+        book.add_game(&game, 10);
+
+        let mut searcher = Searcher::new(Position::new(tables));
+
+        searcher.set_book(Some(Arc::new(book)));
+
+        assert_eq!(searcher.search(&Limits::depth(3)).0, Some(mov));
+        assert_eq!(searcher.get_nodes(), 0);
+
+        // out of the book it searches
+        searcher.set_position(game.position().clone());
+        searcher.set_hash_entries(1000);
+        searcher.search(&Limits::depth(1));
+
+        assert!(searcher.get_nodes() > 0);
+    }
+
+    #[test]
+    fn t_bench() {
+        let nodes = bench(&TABLES, 3);
+
+        assert!(nodes > 0);
+        assert_eq!(bench(&TABLES, 3), nodes);
+    }
+
+    #[test]
+    fn t_send_sync() {
+        fn check<T: Send + Sync + 'static>() {}
+
+        check::<Position>();
+        check::<Game>();
+        check::<Searcher>();
+
+        // a searcher can be moved to another thread and back
+        let mut searcher = Searcher::new(Position::new(&TABLES));
+        let handle = std::thread::spawn(move || {
+            let out = searcher.search(&Limits::depth(2));
+
+            (searcher, out)
         });
+        let (searcher, (mov, _)) = handle.join().unwrap();
+
+        assert!(mov.is_some());
+        assert_eq!(searcher.get_position().board, Board::new());
     }
 }
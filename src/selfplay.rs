@@ -0,0 +1,390 @@
+use crate::gen_tables::*;
+use crate::position::*;
+use crate::search::*;
+use crate::eval::*;
+use crate::game::*;
+
+use rand::seq::SliceRandom;
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+/// One side of a match.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub name: String,
+    pub params: EvalParams,
+    pub skill: usize,
+    /// transposition table size in megabytes
+    pub hash: usize,
+}
+
+impl EngineConfig {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            params: EvalParams::default(),
+            skill: MAX_SKILL,
+            hash: 16,
+        }
+    }
+
+    pub fn searcher(&self, pos: Position) -> Searcher {
+        let mut out = Searcher::new(pos);
+
+        out.set_hash_size(self.hash);
+        out.set_skill(self.skill);
+        out.set_params(self.params.clone());
+        out
+    }
+}
+
+/// A sequential probability ratio test of the hypotheses that engine A is
+/// `elo0` (H0) or `elo1` (H1) Elo stronger than engine B, with error rates
+/// `alpha` and `beta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Self {elo0, elo1, alpha: 0.05, beta: 0.05}
+    }
+
+    /// The log likelihood ratios at which H0 and H1 are accepted.
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(),
+         ((1.0 - self.beta) / self.alpha).ln())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtResult {
+    AcceptH0,
+    AcceptH1,
+    Continue,
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn score_elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Results of a match from engine A's point of view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchStats {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl MatchStats {
+    pub fn add(&mut self, score: f64) {
+        if score > 0.5 {
+            self.wins += 1;
+        } else if score < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    fn variance(&self) -> f64 {
+        let n = self.games().max(1) as f64;
+        let s = self.score();
+
+        (self.wins   as f64 * (1.0 - s).powi(2) +
+         self.draws  as f64 * (0.5 - s).powi(2) +
+         self.losses as f64 * s.powi(2)) / n
+    }
+
+    /// The Elo difference and the half width of its 95% confidence interval.
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, 0.0);
+        }
+
+        let n = self.games() as f64;
+        let s = self.score();
+        let margin = 1.96 * (self.variance() / n).sqrt();
+        let clamp = |x: f64| x.clamp(1e-6, 1.0 - 1e-6);
+
+        let lo = score_elo(clamp(s - margin));
+        let hi = score_elo(clamp(s + margin));
+
+        (score_elo(clamp(s)), (hi - lo) / 2.0)
+    }
+
+    /// The log likelihood ratio of H1 against H0, using a normal
+    /// approximation of the game scores.
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        let variance = self.variance();
+
+        if variance == 0.0 {
+            return 0.0;
+        }
+
+        let s0 = expected_score(sprt.elo0);
+        let s1 = expected_score(sprt.elo1);
+        let n = self.games() as f64;
+
+        n * (s1 - s0) * (2.0 * self.score() - s0 - s1) / (2.0 * variance)
+    }
+
+    pub fn sprt(&self, sprt: &Sprt) -> SprtResult {
+        let llr = self.llr(sprt);
+        let (lower, upper) = sprt.bounds();
+
+        if llr <= lower {
+            SprtResult::AcceptH0
+        } else if llr >= upper {
+            SprtResult::AcceptH1
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+    pub limits: Limits,
+    /// moves after which an unfinished game is decided on points
+    pub max_moves: usize,
+    pub threads: usize,
+    pub sprt: Option<Sprt>,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            limits: Limits::time(100),
+            max_moves: 200,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            sprt: None,
+        }
+    }
+}
+
+/// Up to `count` distinct positions reached by playing `plies` random moves
+/// from the start position.
+pub fn random_openings(tables: &'static Tables, count: usize, plies: usize)
+    -> Vec<Position>
+{
+    let mut rng = rand::thread_rng();
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    let mut moves = Vec::new();
+
+    for _ in 0..count * 100 {
+        if out.len() >= count {
+            break;
+        }
+
+        let mut pos = Position::new(tables);
+
+        for _ in 0..plies {
+            pos.gen_moves(&mut moves);
+
+            match moves.choose(&mut rng) {
+                Some(m) if !m.game_end() => {pos.do_move(*m);}
+                _ => break
+            }
+        }
+
+        if seen.insert(pos.to_fen()) {
+            out.push(pos);
+        }
+    }
+
+    out
+}
+
+/// Plays a game from `start` with `players[0]` as player 1, deciding it on
+/// points after `max_moves` moves.
+pub fn play_game(start: Position,
+                 players: [&mut Searcher; 2],
+                 limits: &Limits,
+                 max_moves: usize)
+    -> Game
+{
+    let mut game = Game::new(start);
+    let [p1, p2] = players;
+
+    while !game.is_over() && game.moves().len() < max_moves {
+        let searcher =
+            if game.position().get_player() {&mut *p2} else {&mut *p1};
+
+        searcher.set_position(game.position().clone());
+
+        match searcher.search(limits).0 {
+            Some(mov) => {game.do_move(mov);}
+            None => break
+        }
+    }
+
+    game
+}
+
+/// A finished game of a match.
+pub struct MatchGame {
+    pub index: usize,
+    pub game: Game,
+    /// whether engine A played player 1
+    pub a_first: bool,
+}
+
+impl MatchGame {
+    pub fn score_a(&self) -> f64 {
+        if self.a_first {
+            self.game.outcome()
+        } else {
+            1.0 - self.game.outcome()
+        }
+    }
+}
+
+/// Plays `games` games between `a` and `b` on all threads of `config`. Game
+/// `i` starts from opening `i / 2`, with the engines swapping colors between
+/// the two games of each opening. `on_game` is called as each game finishes,
+/// and the match stops early once the SPRT, if any, reaches a decision.
+pub fn run_match<F>(openings: &[Position],
+                    a: &EngineConfig,
+                    b: &EngineConfig,
+                    config: &MatchConfig,
+                    games: usize,
+                    on_game: F)
+    -> MatchStats
+    where F: FnMut(&MatchGame, &MatchStats) + Send
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let state = Mutex::new((MatchStats::default(), on_game));
+
+    if openings.is_empty() {
+        return MatchStats::default();
+    }
+
+    thread::scope(|s| {
+        for _ in 0..config.threads.max(1) {
+            s.spawn(|| {
+                let mut searcher_a = a.searcher(openings[0].clone());
+                let mut searcher_b = b.searcher(openings[0].clone());
+
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+
+                    if index >= games || stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let start = openings[index / 2 % openings.len()].clone();
+                    let a_first = index.is_multiple_of(2);
+
+                    let players = if a_first {
+                        [&mut searcher_a, &mut searcher_b]
+                    } else {
+                        [&mut searcher_b, &mut searcher_a]
+                    };
+
+                    let game = play_game(start, players, &config.limits, config.max_moves);
+                    let result = MatchGame {index, game, a_first};
+
+                    let mut state = state.lock().unwrap();
+                    let (stats, on_game) = &mut *state;
+
+                    stats.add(result.score_a());
+                    on_game(&result, stats);
+
+                    if let Some(sprt) = &config.sprt {
+                        if stats.sprt(sprt) != SprtResult::Continue {
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    state.into_inner().unwrap().0
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_match_stats() {
+        let stats = MatchStats {wins: 60, draws: 30, losses: 10};
+        let (elo, margin) = stats.elo();
+
+        assert_eq!(stats.games(), 100);
+        assert!((stats.score() - 0.75).abs() < 1e-9);
+        assert!((elo - 190.85).abs() < 0.01);
+        assert!(margin > 0.0 && margin < elo);
+
+        let sprt = Sprt::new(0.0, 10.0);
+
+        assert!(stats.llr(&sprt) > 0.0);
+        assert_eq!(stats.sprt(&sprt), SprtResult::AcceptH1);
+
+        let stats = MatchStats {wins: 10, draws: 30, losses: 60};
+
+        assert!(stats.llr(&sprt) < 0.0);
+        assert_eq!(stats.sprt(&sprt), SprtResult::AcceptH0);
+
+        let stats = MatchStats {wins: 1, draws: 0, losses: 1};
+
+        assert_eq!(stats.sprt(&sprt), SprtResult::Continue);
+        assert_eq!(MatchStats::default().elo(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn t_run_match() {
+        let tables = &TABLES;
+        let openings = random_openings(tables, 2, 2);
+
+        assert_eq!(openings.len(), 2);
+
+        let mut a = EngineConfig::new("a");
+        let mut b = EngineConfig::new("b");
+
+        a.hash = 1;
+        b.hash = 1;
+        b.skill = 0;
+
+        let config = MatchConfig {
+            limits: Limits::depth(1),
+            max_moves: 10,
+            threads: 2,
+            sprt: None,
+        };
+
+        let mut seen = Vec::new();
+        let stats = run_match(&openings, &a, &b, &config, 4, |game, _| {
+            assert!(game.game.moves().len() <= 10);
+            seen.push((game.index, game.a_first));
+        });
+
+        seen.sort_unstable();
+
+        assert_eq!(stats.games(), 4);
+        assert_eq!(seen, vec![(0, true), (1, false), (2, true), (3, false)]);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::board::*;
+use crate::position::*;
+
+use std::fmt::Write;
+
+/// How `board_svg` draws a board.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvgOptions {
+    /// draw the board from player 2's side
+    pub flipped: bool,
+    /// width of a square in pixels
+    pub square_size: u32,
+    /// label the ranks and files as in move notation
+    pub coordinates: bool,
+    /// moves to draw as arrows, as returned by `Position::get_move`
+    pub arrows: Vec<(usize, usize)>,
+    /// squares to highlight
+    pub highlights: u32,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            flipped: false,
+            square_size: 60,
+            coordinates: true,
+            arrows: Vec::new(),
+            highlights: 0,
+        }
+    }
+}
+
+// the colors and piece sizes of the web page
+const LIGHT: &str = "#f0d9b5";
+const DARK: &str = "#b58863";
+const LIGHT_HIGHLIGHT: &str = "#67bcf7";
+const DARK_HIGHLIGHT: &str = "#2987c9";
+const ARROW: &str = "#15781b";
+const PIECE_COLORS: [&str; 4] = ["", "#ece430", "#352fee", "#ff391a"];
+const PIECE_SIZES: [f64; 4] = [0.0, 0.6, 0.7, 0.8];
+
+impl SvgOptions {
+    fn margin(&self) -> f64 {
+        if self.coordinates {self.square_size as f64 * 0.4} else {0.0}
+    }
+
+    /// The top left corner of `sq`.
+    fn corner(&self, sq: usize) -> (f64, f64) {
+        let (x, y) = (sq % 4, sq / 4);
+        let (col, row) = if self.flipped {(x, y)} else {(3 - x, 7 - y)};
+        let s = self.square_size as f64;
+
+        (self.margin() + col as f64 * s, row as f64 * s)
+    }
+
+    fn center(&self, sq: usize) -> (f64, f64) {
+        let (x, y) = self.corner(sq);
+        let half = self.square_size as f64 / 2.0;
+
+        (x + half, y + half)
+    }
+}
+
+/// Draws `board` as a standalone SVG document: a 4x8 grid with the canal
+/// between the halves, each piece as a pyramid sized by its type, and
+/// optionally coordinates, highlighted squares and arrows.
+pub fn board_svg(board: &Board, options: &SvgOptions) -> String {
+    let s = options.square_size as f64;
+    let margin = options.margin();
+    let width = margin + 4.0 * s;
+    let height = 8.0 * s + margin;
+    let mut out = String::new();
+
+    let _ = writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+                     w = width, h = height);
+    let _ = writeln!(out, r#"<defs><marker id="arrowhead" markerWidth="4" markerHeight="4" refX="2.5" refY="2" orient="auto"><path d="M0,0 L4,2 L0,4 z" fill="{}"/></marker></defs>"#,
+                     ARROW);
+
+    for sq in 0..32 {
+        let (x, y) = options.corner(sq);
+        let dark = (sq % 4 + sq / 4) % 2 == 0;
+        let lit = options.highlights & 1 << sq != 0;
+
+        let fill = match (dark, lit) {
+            (true, false) => DARK,
+            (false, false) => LIGHT,
+            (true, true) => DARK_HIGHLIGHT,
+            (false, true) => LIGHT_HIGHLIGHT,
+        };
+
+        let _ = writeln!(out, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#, x, y, s, s, fill);
+    }
+
+    // the canal
+    let _ = writeln!(out, r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black" stroke-width="{}"/>"#,
+                     margin, 4.0 * s, width, 4.0 * s, s / 15.0);
+
+    for sq in 0..32 {
+        let piece = (board.0 >> sq & 1 | (board.0 >> (sq + 32) & 1) << 1) as usize;
+
+        if piece == 0 {
+            continue;
+        }
+
+        let (x, y) = options.corner(sq);
+        let size = PIECE_SIZES[piece] * s;
+        let left = x + (s - size) / 2.0;
+        let bottom = y + s * 0.9;
+
+        let _ = writeln!(out, r#"<polygon points="{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}" fill="{}" stroke="black" stroke-width="{:.1}"/>"#,
+                         left, bottom, left + size, bottom, left + size / 2.0, bottom - size,
+                         PIECE_COLORS[piece], s / 20.0);
+    }
+
+    if options.coordinates {
+        let font = s * 0.25;
+
+        for sq in 0..4 {
+            // file letters as in stringify_move
+            let (x, _) = options.center(sq);
+            let _ = writeln!(out, r#"<text x="{:.1}" y="{:.1}" font-family="sans-serif" font-size="{:.1}" text-anchor="middle" fill="black">{}</text>"#,
+                             x, 8.0 * s + margin * 0.75, font, "dcba".as_bytes()[sq] as char);
+        }
+
+        for rank in 0..8 {
+            let (_, y) = options.center(rank * 4);
+            let _ = writeln!(out, r#"<text x="{:.1}" y="{:.1}" font-family="sans-serif" font-size="{:.1}" text-anchor="middle" fill="black">{}</text>"#,
+                             margin / 2.0, y + font / 3.0, font, rank + 1);
+        }
+    }
+
+    for &(from, to) in options.arrows.iter().filter(|(f, t)| *f < 32 && *t < 32) {
+        let (x1, y1) = options.center(from);
+        let (x2, y2) = options.center(to);
+
+        // stop short of the center of the target so the head stays on it
+        let len = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt().max(1.0);
+        let back = s * 0.3 / len;
+
+        let _ = writeln!(out, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{}" stroke-width="{:.1}" stroke-opacity="0.8" marker-end="url(#arrowhead)"/>"#,
+                         x1, y1, x2 - (x2 - x1) * back, y2 - (y2 - y1) * back, ARROW, s / 8.0);
+    }
+
+    out += "</svg>\n";
+    out
+}
+
+/// Draws `pos` with the squares of the last move highlighted, if it has
+/// one.
+pub fn position_svg(pos: &Position, options: &SvgOptions) -> String {
+    let mut options = options.clone();
+
+    let prev = pos.get_prev();
+
+    if prev != Board::empty() && prev != pos.board {
+        let (from, to) = pos.get_move();
+
+        if from < 32 && to < 32 {
+            options.highlights |= 1 << from | 1 << to;
+        }
+    }
+
+    board_svg(&pos.board, &options)
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::gen_tables::*;
+    use super::*;
+
+    #[test]
+    fn t_board_svg() {
+        let board = Board::new();
+        let svg = board_svg(&board, &SvgOptions::default());
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<polygon").count(), board.occ().count_ones() as usize);
+        assert_eq!(svg.matches("<rect").count(), 32);
+        assert_eq!(svg.matches("<text").count(), 12);
+        assert_eq!(svg.matches(PIECE_COLORS[3]).count(), board.queens().count_ones() as usize);
+        assert!(!svg.contains("marker-end"));
+
+        let options = SvgOptions {
+            coordinates: false,
+            arrows: vec![parse_move("c3b4").unwrap(), (32, 0)],
+            highlights: 1 << parse_square("a1").unwrap(),
+            ..SvgOptions::default()
+        };
+        let svg = board_svg(&board, &options);
+
+        assert_eq!(svg.matches("<text").count(), 0);
+        assert_eq!(svg.matches("marker-end").count(), 1);
+        assert_eq!(svg.matches(LIGHT_HIGHLIGHT).count() + svg.matches(DARK_HIGHLIGHT).count(), 1);
+        assert!(svg.contains(r#"width="240" height="480""#));
+    }
+
+    #[test]
+    fn t_position_svg() {
+        let tables = &TABLES;
+        let mut pos = Position::new(tables);
+
+        let count = |svg: &str| svg.matches(LIGHT_HIGHLIGHT).count() + svg.matches(DARK_HIGHLIGHT).count();
+
+        assert_eq!(count(&position_svg(&pos, &SvgOptions::default())), 0);
+
+        assert!(pos.do_string_move("c3b4"));
+        assert_eq!(count(&position_svg(&pos, &SvgOptions::default())), 2);
+
+        // no last move to show when the previous board is missing or the
+        // same as the current one
+        let empty = Position::from_fen("0000000000000000 0000000000000000 1 0", tables);
+        let board = Board::new().0;
+        let same = Position::from_fen(&format!("{:016x} {:016x} 1 0", board, board), tables);
+
+        assert_eq!(count(&position_svg(&empty, &SvgOptions::default())), 0);
+        assert_eq!(count(&position_svg(&same, &SvgOptions::default())), 0);
+    }
+}
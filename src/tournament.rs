@@ -0,0 +1,500 @@
+use crate::position::*;
+use crate::game::*;
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How to start an engine: a name for the crosstable, the program and its
+/// arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineSpec {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+impl EngineSpec {
+    /// Parses `NAME=COMMAND ARGS...`, or just `COMMAND ARGS...` in which
+    /// case the command is used as the name.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (name, command) = match s.find('=') {
+            Some(i) => (s[..i].trim().to_string(), &s[i + 1..]),
+            None => (String::new(), s),
+        };
+
+        let mut words = command.split_whitespace().map(|w| w.to_string());
+        let command = words.next()?;
+        let name = if name.is_empty() {command.clone()} else {name};
+
+        Some(Self {name, command, args: words.collect()})
+    }
+}
+
+/// Why an engine lost a game without it being played out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    Timeout,
+    IllegalMove(String),
+    Crashed,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Timeout => write!(f, "lost on time"),
+            EngineError::IllegalMove(m) => write!(f, "illegal move {}", m),
+            EngineError::Crashed => write!(f, "engine crashed"),
+        }
+    }
+}
+
+/// A running engine, spoken to over the UCI-style protocol of the
+/// `martian_engine` binary.
+pub struct EngineProcess {
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl EngineProcess {
+    pub fn start(spec: &EngineSpec) -> io::Result<Self> {
+        let mut child = Command::new(&spec.command)
+            .args(&spec.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (tx, lines) = mpsc::channel();
+
+        // a reader thread lets responses be waited for with a timeout
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() {break},
+                    Err(_) => break
+                }
+            }
+        });
+
+        let mut out = Self {name: spec.name.clone(), child, stdin, lines};
+
+        out.send("uci")?;
+        out.wait_for("uciok", Duration::from_secs(10))?;
+        out.new_game()?;
+
+        Ok(out)
+    }
+
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", line)?;
+        self.stdin.flush()
+    }
+
+    /// Waits for a line starting with `prefix`, discarding any others.
+    fn wait_for(&mut self, prefix: &str, timeout: Duration) -> io::Result<String> {
+        let start = Instant::now();
+
+        loop {
+            let left = timeout.checked_sub(start.elapsed()).unwrap_or_default();
+
+            match self.lines.recv_timeout(left) {
+                Ok(line) if line.starts_with(prefix) => return Ok(line),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, prefix.to_string()))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, prefix.to_string()))
+                }
+            }
+        }
+    }
+
+    /// Resets the engine for a new game, also draining any output left over
+    /// from a game it forfeited.
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.wait_for("readyok", Duration::from_secs(10))?;
+        Ok(())
+    }
+
+    /// Asks for a move in the current position of `game`, given the
+    /// remaining time and increment of each player in milliseconds. Returns
+    /// the move and the time it took.
+    pub fn go(&mut self, game: &Game, clocks: [u64; 2], inc: u64)
+        -> Result<(String, Duration), EngineError>
+    {
+        let mut position = format!("position fen {}", game.start().to_fen());
+
+        if !game.moves().is_empty() {
+            position += " moves ";
+            position += &game.move_strings().join(" ");
+        }
+
+        let go = format!("go p1time {} p2time {} p1inc {} p2inc {}",
+                         clocks[0], clocks[1], inc, inc);
+
+        self.send(&position).and_then(|_| self.send(&go))
+            .map_err(|_| EngineError::Crashed)?;
+
+        let start = Instant::now();
+        let clock = clocks[game.position().get_player() as usize];
+
+        match self.wait_for("bestmove", Duration::from_millis(clock)) {
+            Ok(line) => {
+                let mov = line.split_whitespace().nth(1).unwrap_or("").to_string();
+                Ok((mov, start.elapsed()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                let _ = self.send("stop");
+                Err(EngineError::Timeout)
+            }
+            Err(_) => Err(EngineError::Crashed)
+        }
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+
+        let start = Instant::now();
+
+        while start.elapsed() < Duration::from_secs(1) {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Time for each player's whole game plus an increment per move, in
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub base: u64,
+    pub increment: u64,
+}
+
+impl TimeControl {
+    /// Parses `BASE` or `BASE+INCREMENT`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('+');
+        let base = parts.next()?.trim().parse().ok()?;
+        let increment = match parts.next() {
+            Some(i) => i.trim().parse().ok()?,
+            None => 0,
+        };
+
+        Some(Self {base, increment})
+    }
+}
+
+/// How a game between external engines ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Termination {
+    /// one half of the board was emptied
+    GameEnd,
+    /// the move limit was reached and the game decided on points
+    MoveLimit,
+    /// a player, 0 or 1, forfeited
+    Forfeit(usize, EngineError),
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::GameEnd => write!(f, "game end"),
+            Termination::MoveLimit => write!(f, "move limit"),
+            Termination::Forfeit(p, e) => write!(f, "player {} {}", p + 1, e),
+        }
+    }
+}
+
+/// Plays a game between two engine processes, `engines[0]` as player 1.
+/// Returns the game, how it ended, and the result for player 1.
+pub fn play_engine_game(engines: [&mut EngineProcess; 2],
+                        start: Position,
+                        tc: &TimeControl,
+                        max_moves: usize)
+    -> (Game, Termination, f64)
+{
+    let mut game = Game::new(start);
+    let mut clocks = [tc.base; 2];
+    let [p1, p2] = engines;
+
+    if p1.new_game().is_err() {
+        return (game, Termination::Forfeit(0, EngineError::Crashed), 0.0);
+    }
+
+    if p2.new_game().is_err() {
+        return (game, Termination::Forfeit(1, EngineError::Crashed), 1.0);
+    }
+
+    loop {
+        if game.is_over() {
+            let result = game.outcome();
+            return (game, Termination::GameEnd, result);
+        }
+
+        if game.moves().len() >= max_moves {
+            let result = game.outcome();
+            return (game, Termination::MoveLimit, result);
+        }
+
+        let player = game.position().get_player() as usize;
+        let engine = if player == 0 {&mut *p1} else {&mut *p2};
+
+        let forfeit = match engine.go(&game, clocks, tc.increment) {
+            Ok((mov, used)) => {
+                let used = used.as_millis() as u64;
+
+                if used > clocks[player] {
+                    Some(EngineError::Timeout)
+                } else if !game.do_string_move(&mov) {
+                    Some(EngineError::IllegalMove(mov))
+                } else {
+                    clocks[player] = clocks[player] - used + tc.increment;
+                    None
+                }
+            }
+            Err(e) => Some(e)
+        };
+
+        if let Some(e) = forfeit {
+            return (game, Termination::Forfeit(player, e), player as f64);
+        }
+    }
+}
+
+/// Scores of a round robin: `scores[i][j]` is what engine `i` scored
+/// against engine `j` in `games[i][j]` games.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crosstable {
+    pub names: Vec<String>,
+    pub scores: Vec<Vec<f64>>,
+    pub games: Vec<Vec<usize>>,
+}
+
+impl Crosstable {
+    pub fn new(names: Vec<String>) -> Self {
+        let n = names.len();
+
+        Self {
+            names,
+            scores: vec![vec![0.0; n]; n],
+            games: vec![vec![0; n]; n],
+        }
+    }
+
+    /// Records a game between engines `p1` and `p2` with `result` for `p1`.
+    pub fn add(&mut self, p1: usize, p2: usize, result: f64) {
+        self.scores[p1][p2] += result;
+        self.scores[p2][p1] += 1.0 - result;
+        self.games[p1][p2] += 1;
+        self.games[p2][p1] += 1;
+    }
+
+    pub fn total(&self, i: usize) -> f64 {
+        self.scores[i].iter().sum()
+    }
+
+    pub fn total_games(&self, i: usize) -> usize {
+        self.games[i].iter().sum()
+    }
+
+    /// Engine indices from the highest total score to the lowest.
+    pub fn ranking(&self) -> Vec<usize> {
+        let mut out = (0..self.names.len()).collect::<Vec<_>>();
+
+        out.sort_by(|&a, &b| self.total(b).partial_cmp(&self.total(a)).unwrap());
+        out
+    }
+}
+
+impl fmt::Display for Crosstable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let order = self.ranking();
+        let width = self.names.iter().map(|n| n.len()).max().unwrap_or(0).max(6);
+
+        write!(f, "{:>3}  {:<width$}", "#", "engine", width = width)?;
+
+        for i in 1..=order.len() {
+            write!(f, " {:>6}", i)?;
+        }
+
+        writeln!(f, " {:>8}", "score")?;
+
+        for (rank, &i) in order.iter().enumerate() {
+            write!(f, "{:>3}  {:<width$}", rank + 1, self.names[i], width = width)?;
+
+            for &j in order.iter() {
+                if i == j {
+                    write!(f, " {:>6}", "-")?;
+                } else {
+                    write!(f, " {:>6}", self.scores[i][j])?;
+                }
+            }
+
+            writeln!(f, " {:>4}/{:<3}", self.total(i), self.total_games(i))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TournamentConfig {
+    pub time_control: TimeControl,
+    /// games each pair of engines plays, alternating who is player 1
+    pub games_per_pair: usize,
+    pub max_moves: usize,
+}
+
+/// One finished tournament game.
+pub struct TournamentGame {
+    pub p1: usize,
+    pub p2: usize,
+    pub game: Game,
+    pub termination: Termination,
+    pub result: f64,
+}
+
+/// Plays a round robin between `engines`, one game at a time so that the
+/// engines do not compete for the CPU. Each pairing cycles through
+/// `openings`, and `on_game` is called as each game finishes.
+pub fn run_tournament<F>(engines: &mut [EngineProcess],
+                         openings: &[Position],
+                         config: &TournamentConfig,
+                         mut on_game: F)
+    -> Crosstable
+    where F: FnMut(&TournamentGame, &Crosstable)
+{
+    let mut table = Crosstable::new(engines.iter().map(|e| e.name.clone()).collect());
+
+    if openings.is_empty() {
+        return table;
+    }
+
+    for i in 0..engines.len() {
+        for j in i + 1..engines.len() {
+            for g in 0..config.games_per_pair {
+                let (p1, p2) = if g % 2 == 0 {(i, j)} else {(j, i)};
+                let start = openings[g / 2 % openings.len()].clone();
+
+                let (left, right) = engines.split_at_mut(p1.max(p2));
+                let (e1, e2) = if p1 < p2 {
+                    (&mut left[p1], &mut right[0])
+                } else {
+                    (&mut right[0], &mut left[p2])
+                };
+
+                let (game, termination, result) =
+                    play_engine_game([e1, e2], start, &config.time_control, config.max_moves);
+
+                table.add(p1, p2, result);
+                on_game(&TournamentGame {p1, p2, game, termination, result}, &table);
+            }
+        }
+    }
+
+    table
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::gen_tables::*;
+    use super::*;
+
+    #[test]
+    fn t_parse_specs() {
+        assert_eq!(EngineSpec::parse("old=./engine --skill 3").unwrap(), EngineSpec {
+            name: "old".to_string(),
+            command: "./engine".to_string(),
+            args: vec!["--skill".to_string(), "3".to_string()],
+        });
+
+        assert_eq!(EngineSpec::parse("./engine").unwrap().name, "./engine");
+        assert!(EngineSpec::parse("name=").is_none());
+
+        assert_eq!(TimeControl::parse("1000+50"),
+                   Some(TimeControl {base: 1000, increment: 50}));
+        assert_eq!(TimeControl::parse("1000"),
+                   Some(TimeControl {base: 1000, increment: 0}));
+        assert_eq!(TimeControl::parse("x+1"), None);
+    }
+
+    #[test]
+    fn t_crosstable() {
+        let mut table = Crosstable::new(vec!["a".into(), "b".into(), "c".into()]);
+
+        table.add(0, 1, 1.0);
+        table.add(1, 0, 0.5);
+        table.add(2, 0, 1.0);
+        table.add(1, 2, 0.0);
+
+        assert_eq!(table.total(0), 1.5);
+        assert_eq!(table.total(1), 0.5);
+        assert_eq!(table.total(2), 2.0);
+        assert_eq!(table.total_games(0), 3);
+        assert_eq!(table.ranking(), vec![2, 0, 1]);
+
+        let text = table.to_string();
+
+        assert_eq!(text.lines().count(), 4);
+        assert!(text.lines().nth(1).unwrap().contains("c"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn t_forfeit() {
+        // an engine that only knows how to answer with an illegal move
+        let script = "while read cmd rest; do case $cmd in \
+                      uci) echo uciok;; isready) echo readyok;; \
+                      go) echo bestmove a1a1;; quit) exit;; esac; done";
+
+        let spec = EngineSpec {
+            name: "bad".to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+        };
+
+        let tables = &TABLES;
+        let mut engines = vec![
+            EngineProcess::start(&spec).unwrap(),
+            EngineProcess::start(&spec).unwrap(),
+        ];
+
+        let config = TournamentConfig {
+            time_control: TimeControl {base: 5000, increment: 0},
+            games_per_pair: 2,
+            max_moves: 10,
+        };
+
+        let mut terminations = Vec::new();
+        let table = run_tournament(&mut engines, &[Position::new(tables)], &config, |g, _| {
+            terminations.push(g.termination.clone());
+        });
+
+        // player 1 forfeits each game
+        assert_eq!(terminations, vec![
+            Termination::Forfeit(0, EngineError::IllegalMove("a1a1".to_string())),
+            Termination::Forfeit(0, EngineError::IllegalMove("a1a1".to_string())),
+        ]);
+        assert_eq!(table.total(0), 1.0);
+        assert_eq!(table.total(1), 1.0);
+    }
+}
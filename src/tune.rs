@@ -0,0 +1,331 @@
+use crate::gen_tables::*;
+use crate::position::*;
+use crate::search::*;
+use crate::eval::*;
+
+use std::thread;
+
+/// A quiet training position: its evaluation features from player 1's point
+/// of view and the game result for player 1 (1 win, 0.5 draw, 0 loss).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub features: Vec<(u16, i16)>,
+    pub result: f64,
+}
+
+/// Parses a result as `1-0`, `0-1`, `1/2-1/2` or a number from 0 to 1, all
+/// from player 1's point of view.
+pub fn parse_result(s: &str) -> Option<f64> {
+    match s {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" | "1/2" => Some(0.5),
+        _ => s.parse().ok().filter(|r| (0.0..=1.0).contains(r)),
+    }
+}
+
+/// Parses a line holding a position as written by `Position::to_fen`
+/// followed by the result of the game it came from.
+pub fn parse_sample_line(line: &str, tables: &'static Tables)
+    -> Option<(Position, f64)>
+{
+    let words = line.split_whitespace().collect::<Vec<_>>();
+
+    if words.len() != 5 && words.len() != 7 {
+        return None;
+    }
+
+    let (result, fen) = words.split_last()?;
+
+    Some((Position::parse_fen(&fen.join(" "), tables)?, parse_result(result)?))
+}
+
+fn num_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Turns labelled positions into samples, replacing each position with the
+/// quiet position found by `Searcher::quiet_position` first. Work is split
+/// over all cores.
+pub fn make_samples(positions: &[(Position, f64)]) -> Vec<Sample> {
+    let chunk = (positions.len() / num_threads()).max(1);
+
+    thread::scope(|s| {
+        let handles = positions
+            .chunks(chunk)
+            .map(|chunk| s.spawn(move || {
+                let mut searcher = None;
+                let mut out = Vec::with_capacity(chunk.len());
+
+                for (pos, result) in chunk {
+                    let searcher = searcher
+                        .get_or_insert_with(|| Searcher::new(pos.clone()));
+
+                    searcher.set_position(pos.clone());
+
+                    let quiet = searcher.quiet_position();
+                    let sign = if quiet.get_player() {-1} else {1};
+
+                    let features = EvalParams::features(&quiet)
+                        .into_iter()
+                        .map(|(i, x)| (i as u16, (x * sign) as i16))
+                        .collect();
+
+                    out.push(Sample {features, result: *result});
+                }
+
+                out
+            }))
+            .collect::<Vec<_>>();
+
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+fn sample_eval(sample: &Sample, weights: &[f64]) -> f64 {
+    sample.features.iter().map(|&(i, x)| weights[i as usize] * x as f64).sum()
+}
+
+/// Fits evaluation weights to game results by minimizing the mean squared
+/// error between each result and `1 / (1 + 10^(-k * eval / 400))`, as in
+/// Texel's tuning method. Since the evaluation is linear in its weights the
+/// gradient is exact, and the weights are optimized with Adam.
+pub struct Tuner {
+    samples: Vec<Sample>,
+    k: f64,
+    threads: usize,
+}
+
+impl Tuner {
+    pub fn new(samples: Vec<Sample>) -> Self {
+        Self {
+            samples,
+            k: 1.0,
+            threads: num_threads(),
+        }
+    }
+
+    pub fn get_k(&self) -> f64 {
+        self.k
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn chunk_size(&self) -> usize {
+        (self.samples.len() / self.threads).max(1)
+    }
+
+    pub fn error(&self, weights: &[f64]) -> f64 {
+        let k = self.k;
+
+        let total: f64 = thread::scope(|s| {
+            let handles = self.samples
+                .chunks(self.chunk_size())
+                .map(|chunk| s.spawn(move || {
+                    chunk.iter()
+                        .map(|sample| {
+                            let d = sample.result - sigmoid(k, sample_eval(sample, weights));
+                            d * d
+                        })
+                        .sum::<f64>()
+                }))
+                .collect::<Vec<_>>();
+
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        total / self.samples.len().max(1) as f64
+    }
+
+    fn gradient(&self, weights: &[f64]) -> Vec<f64> {
+        let k = self.k;
+        let scale = k * 10f64.ln() / 400.0;
+
+        let total = thread::scope(|s| {
+            let handles = self.samples
+                .chunks(self.chunk_size())
+                .map(|chunk| s.spawn(move || {
+                    let mut grad = vec![0.0; weights.len()];
+
+                    for sample in chunk {
+                        let p = sigmoid(k, sample_eval(sample, weights));
+                        let d = (p - sample.result) * p * (1.0 - p) * scale;
+
+                        for &(i, x) in sample.features.iter() {
+                            grad[i as usize] += d * x as f64;
+                        }
+                    }
+
+                    grad
+                }))
+                .collect::<Vec<_>>();
+
+            handles.into_iter().fold(vec![0.0; weights.len()], |mut acc, h| {
+                for (a, g) in acc.iter_mut().zip(h.join().unwrap()) {
+                    *a += g;
+                }
+                acc
+            })
+        });
+
+        let n = self.samples.len().max(1) as f64;
+
+        total.into_iter().map(|g| 2.0 * g / n).collect()
+    }
+
+    /// Picks the scaling constant `k` that best fits the results with the
+    /// given weights, by ternary search. The weights are then tuned with `k`
+    /// held fixed.
+    pub fn fit_k(&mut self, params: &EvalParams) -> f64 {
+        let weights = to_f64(params);
+
+        let mut lo = 0.0;
+        let mut hi = 10.0;
+
+        for _ in 0..50 {
+            let m1 = lo + (hi - lo) / 3.0;
+            let m2 = hi - (hi - lo) / 3.0;
+
+            self.k = m1;
+            let e1 = self.error(&weights);
+            self.k = m2;
+            let e2 = self.error(&weights);
+
+            if e1 < e2 {
+                hi = m2;
+            } else {
+                lo = m1;
+            }
+        }
+
+        self.k = (lo + hi) / 2.0;
+        self.k
+    }
+
+    /// Runs `iterations` steps of Adam with learning rate `rate` starting
+    /// from `params`, calling `progress` with the iteration number and the
+    /// current error every `report` iterations.
+    pub fn tune<F>(&self,
+                   params: &EvalParams,
+                   iterations: usize,
+                   rate: f64,
+                   report: usize,
+                   mut progress: F)
+        -> EvalParams
+        where F: FnMut(usize, f64)
+    {
+        const BETA1: f64 = 0.9;
+        const BETA2: f64 = 0.999;
+        const EPSILON: f64 = 1e-8;
+
+        let mut weights = to_f64(params);
+        let mut m = vec![0.0; weights.len()];
+        let mut v = vec![0.0; weights.len()];
+
+        for t in 1..=iterations {
+            let grad = self.gradient(&weights);
+
+            let c1 = 1.0 - BETA1.powi(t as i32);
+            let c2 = 1.0 - BETA2.powi(t as i32);
+
+            for i in 0..weights.len() {
+                m[i] = BETA1 * m[i] + (1.0 - BETA1) * grad[i];
+                v[i] = BETA2 * v[i] + (1.0 - BETA2) * grad[i] * grad[i];
+
+                weights[i] -= rate * (m[i] / c1) / ((v[i] / c2).sqrt() + EPSILON);
+            }
+
+            if report != 0 && t % report == 0 {
+                progress(t, self.error(&weights));
+            }
+        }
+
+        let rounded = weights.iter().map(|w| w.round() as i32).collect::<Vec<_>>();
+
+        params.from_weights(&rounded)
+    }
+}
+
+fn to_f64(params: &EvalParams) -> Vec<f64> {
+    params.to_weights().into_iter().map(|w| w as f64).collect()
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::board::*;
+    use super::*;
+
+    #[test]
+    fn t_parse_sample_line() {
+        let tables = &TABLES;
+
+        let (pos, result) = parse_sample_line(
+            "ec200a27ca406643 0000000000000000 2 3 0-1", tables
+        ).unwrap();
+
+        assert_eq!(pos.to_fen(), "ec200a27ca406643 0000000000000000 2 3 0 0");
+        assert_eq!(result, 0.0);
+
+        let (_, result) = parse_sample_line(
+            "ec200a27ca406643 0000000000000000 1 0 0.5", tables
+        ).unwrap();
+
+        assert_eq!(result, 0.5);
+
+        let (pos, result) = parse_sample_line(
+            "ec200a27ca406643 0000000000000000 2 3 7 20 1", tables
+        ).unwrap();
+
+        assert_eq!(pos.get_move_count(), 20);
+        assert_eq!(result, 1.0);
+
+        assert!(parse_sample_line("ec200a27ca406643 0 1 0", tables).is_none());
+        assert!(parse_sample_line("ec200a27ca406643 0 3 0 1-0", tables).is_none());
+        assert!(parse_sample_line("ec200a27ca406643 0 1 0 2", tables).is_none());
+        assert!(parse_sample_line("xyz 0 1 0 1-0", tables).is_none());
+    }
+
+    #[test]
+    fn t_tune() {
+        let tables = &TABLES;
+
+        // player 1 wins whenever they are ahead on points, so tuning should
+        // raise the weight of the captured score
+        let mut positions = Vec::new();
+
+        for score in -5..=5 {
+            for &player in ["1", "2"].iter() {
+                let fen = format!("{:016x} 0 {} {}", Board::new().0, player, score);
+                let result = if score > 0 {1.0} else if score < 0 {0.0} else {0.5};
+
+                positions.push((Position::from_fen(&fen, tables), result));
+            }
+        }
+
+        let samples = make_samples(&positions);
+
+        assert_eq!(samples.len(), positions.len());
+
+        let mut tuner = Tuner::new(samples);
+        let start = EvalParams {captured: 10, ..EvalParams::default()};
+
+        tuner.fit_k(&start);
+
+        let before = tuner.error(&to_f64(&start));
+        let tuned = tuner.tune(&start, 200, 2.0, 0, |_, _| {});
+        let after = tuner.error(&to_f64(&tuned));
+
+        assert!(after < before);
+        assert!(tuned.captured > start.captured);
+    }
+}
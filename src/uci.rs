@@ -0,0 +1,572 @@
+use crate::gen_tables::*;
+use crate::position::*;
+use crate::search::*;
+use crate::eval::*;
+use crate::game::*;
+use crate::rules::*;
+use crate::tablebase::*;
+use crate::book::*;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, Scope, ScopedJoinHandle};
+
+const MAX_HASH: usize = 65536;
+const MAX_CONTEMPT: i32 = 1000;
+const MAX_LEAD: i32 = 1000;
+const MAX_MOVE_LIMIT: u32 = 100000;
+
+/// Engine settings changed with `setoption`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UciOptions {
+    /// transposition table size in megabytes
+    pub hash: usize,
+    pub skill: usize,
+    /// see `Searcher::set_contempt`
+    pub contempt: i32,
+    pub params: EvalParams,
+    pub tablebase: Option<Arc<Tablebase>>,
+    pub book: Option<Arc<Book>>,
+    /// the rules of the games set up by `position` and `ucinewgame`
+    pub rules: RuleSet,
+}
+
+impl Default for UciOptions {
+    fn default() -> Self {
+        Self {
+            hash: 16,
+            skill: MAX_SKILL,
+            contempt: 0,
+            params: EvalParams::default(),
+            tablebase: None,
+            book: None,
+            rules: RuleSet::STANDARD,
+        }
+    }
+}
+
+impl UciOptions {
+    /// The `option` lines sent in reply to `uci`.
+    pub fn describe(&self) -> Vec<String> {
+        vec![
+            format!("option name Hash type spin default {} min 1 max {}", self.hash, MAX_HASH),
+            format!("option name Skill type spin default {} min 0 max {}", self.skill, MAX_SKILL),
+            format!("option name Contempt type spin default {} min {} max {}",
+                    self.contempt, -MAX_CONTEMPT, MAX_CONTEMPT),
+            "option name EvalFile type string default <empty>".to_string(),
+            "option name TablebaseFile type string default <empty>".to_string(),
+            "option name BookFile type string default <empty>".to_string(),
+            format!("option name FieldPromotion type check default {}", self.rules.field_promotion),
+            format!("option name NoReversal type check default {}", self.rules.no_reversal),
+            format!("option name EndWithoutMoves type check default {}",
+                    self.rules.end_without_moves),
+            format!("option name WinningLead type spin default {} min 0 max {}",
+                    self.rules.winning_lead.unwrap_or(0), MAX_LEAD),
+            format!("option name NoCaptureLimit type spin default {} min 0 max {}",
+                    self.rules.no_capture_limit.unwrap_or(0), MAX_MOVE_LIMIT),
+            format!("option name MoveLimit type spin default {} min 0 max {}",
+                    self.rules.move_limit.unwrap_or(0), MAX_MOVE_LIMIT),
+            format!("option name RepetitionDraw type check default {}", self.rules.repetition_draw),
+        ]
+    }
+
+    /// Sets the option called `name`, ignoring case as UCI does. An empty
+    /// `EvalFile` restores the default evaluation, an empty `TablebaseFile`
+    /// or `BookFile` plays without tables or book, and a `WinningLead`,
+    /// `NoCaptureLimit` or `MoveLimit` of 0 plays without that limit.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let number = || value.parse::<usize>()
+            .map_err(|_| format!("bad value for {}: {}", name, value));
+        let rule = |rules: &mut RuleSet, field, value| rules.set(field, value)
+            .map_err(|_| format!("bad value for {}: {}", name, value));
+
+        match name.to_lowercase().as_str() {
+            "hash" => self.hash = number()?.clamp(1, MAX_HASH),
+            "skill" => self.skill = number()?.min(MAX_SKILL),
+            "contempt" => {
+                self.contempt = value.parse::<i32>()
+                    .map_err(|_| format!("bad value for {}: {}", name, value))?
+                    .clamp(-MAX_CONTEMPT, MAX_CONTEMPT)
+            }
+            "evalfile" => {
+                self.params = match value {
+                    "" | "<empty>" => EvalParams::default(),
+                    path => EvalParams::load(path)
+                        .map_err(|e| format!("{}: {}", path, e))?,
+                }
+            }
+            "tablebasefile" => {
+                self.tablebase = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Arc::new(Tablebase::load(path)
+                        .map_err(|e| format!("{}: {}", path, e))?)),
+                }
+            }
+            "bookfile" => {
+                self.book = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Arc::new(Book::load(path)
+                        .map_err(|e| format!("{}: {}", path, e))?)),
+                }
+            }
+            "fieldpromotion" => rule(&mut self.rules, "field_promotion", value)?,
+            "noreversal" => rule(&mut self.rules, "no_reversal", value)?,
+            "endwithoutmoves" => rule(&mut self.rules, "end_without_moves", value)?,
+            "winninglead" => {
+                let lead = number()?.min(MAX_LEAD as usize).to_string();
+                rule(&mut self.rules, "winning_lead", &lead)?
+            }
+            "nocapturelimit" => {
+                let limit = number()?.min(MAX_MOVE_LIMIT as usize).to_string();
+                rule(&mut self.rules, "no_capture_limit", &limit)?
+            }
+            "movelimit" => {
+                let limit = number()?.min(MAX_MOVE_LIMIT as usize).to_string();
+                rule(&mut self.rules, "move_limit", &limit)?
+            }
+            "repetitiondraw" => rule(&mut self.rules, "repetition_draw", value)?,
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+
+        Ok(())
+    }
+
+    /// The tablebase to search with in games played by `rules`, or an error
+    /// if one is set but was not solved for them.
+    pub fn tablebase_for(&self, rules: &RuleSet) -> Result<Option<Arc<Tablebase>>, String> {
+        match &self.tablebase {
+            Some(_) if *rules != TABLEBASE_RULES => {
+                Err("the tablebase is not used: it needs NoReversal off and the other rules \
+                     left as standard".to_string())
+            }
+            tablebase => Ok(tablebase.clone()),
+        }
+    }
+}
+
+/// Parses the arguments of `position`: `startpos` or `fen` followed by the
+/// words of `Position::to_fen`, then optionally `moves` and a list of moves,
+/// which are played under `rules`. Fens without the move counters are
+/// accepted too.
+pub fn parse_position(words: &[&str], tables: &'static Tables, rules: &RuleSet)
+    -> Result<Game, String>
+{
+    let (start, rest) = match words {
+        ["startpos", rest @ ..] => (Position::new(tables), rest),
+        ["fen", rest @ ..] if rest.len() >= 4 => {
+            let len = Position::fen_words(rest);
+            let fen = rest[..len].join(" ");
+            let pos = Position::parse_fen(&fen, tables)
+                .ok_or_else(|| format!("bad fen: {}", fen))?;
+
+            (pos, &rest[len..])
+        }
+        _ => return Err("expected startpos or fen".to_string()),
+    };
+
+    let mut game = Game::new(start.with_rules(*rules));
+
+    match rest {
+        [] => {}
+        ["moves", moves @ ..] => {
+            for m in moves {
+                if !game.do_string_move(m) {
+                    return Err(format!("illegal move: {}", m));
+                }
+            }
+        }
+        _ => return Err(format!("unexpected: {}", rest.join(" "))),
+    }
+
+    Ok(game)
+}
+
+/// Parses the arguments of `go` into limits for a search by `player`.
+/// `p1time`/`p2time` and `p1inc`/`p2inc` give each player's clock and
+/// increment in milliseconds (`wtime`, `btime`, `winc` and `binc` are
+/// accepted for player 1 and 2 too), from which a time for this move is
+/// budgeted. `go` with no limits, like `go infinite`, searches until
+/// `stop`.
+pub fn parse_go(words: &[&str], player: bool) -> Limits {
+    let mut limits = Limits::default();
+    let mut clock = [None; 2];
+    let mut inc = [0; 2];
+    let mut moves_to_go = 25;
+
+    for pair in words.windows(2) {
+        let value = match pair[1].parse::<usize>() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        match pair[0] {
+            "movetime" => limits.time = value,
+            "depth" => limits.depth = value.max(1),
+            "nodes" => limits.nodes = value,
+            "p1time" | "wtime" => clock[0] = Some(value),
+            "p2time" | "btime" => clock[1] = Some(value),
+            "p1inc" | "winc" => inc[0] = value,
+            "p2inc" | "binc" => inc[1] = value,
+            "movestogo" => moves_to_go = value.max(1),
+            _ => {}
+        }
+    }
+
+    if let Some(left) = clock[player as usize] {
+        let budget = left / moves_to_go + inc[player as usize] / 2;
+
+        // never plan to use more than half of what is left
+        limits.time = limits.time.min(budget.min(left / 2).max(1));
+    }
+
+    limits
+}
+
+fn info_line(start: &Position, info: &SearchInfo) -> String {
+    let nps = info.nodes as u128 * 1000 / (info.time as u128).max(1);
+
+    format!("info depth {} score cp {} nodes {} time {} nps {} pv {}",
+            info.depth, info.score, info.nodes, info.time, nps,
+            line_strings(start, &info.pv).join(" "))
+}
+
+fn send<W: Write>(out: &Mutex<W>, line: &str) {
+    let mut out = out.lock().unwrap();
+
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+/// Searches, sending an `info` line for each iteration and then the move.
+fn run_search<W: Write>(searcher: &mut Searcher, limits: &Limits, out: &Mutex<W>) {
+    let start = searcher.get_position().clone();
+    let (best, _) = searcher.search_with_info(limits, |info| {
+        send(out, &info_line(&start, info));
+    });
+
+    match best {
+        Some(m) => send(out, &format!("bestmove {}", line_strings(&start, &[m])[0])),
+        None => send(out, "bestmove 0000"),
+    }
+}
+
+struct Running<'scope> {
+    stop: Arc<AtomicBool>,
+    infinite: bool,
+    handle: ScopedJoinHandle<'scope, Searcher>,
+}
+
+/// Holds the state between commands of the engine protocol.
+struct Engine<'scope> {
+    tables: &'static Tables,
+    options: UciOptions,
+    game: Game,
+    /// created on the first `go` after the hash size changes
+    searcher: Option<Searcher>,
+    running: Option<Running<'scope>>,
+}
+
+impl<'scope> Engine<'scope> {
+    /// Waits for the running search, if any, to send its move, first telling
+    /// it to stop if `stop` is set.
+    fn finish(&mut self, stop: bool) {
+        if let Some(running) = self.running.take() {
+            if stop {
+                running.stop.store(true, Ordering::Relaxed);
+            }
+
+            self.searcher = Some(running.handle.join().unwrap());
+        }
+    }
+
+    fn go<'env, W>(&mut self, s: &'scope Scope<'scope, 'env>, words: &[&str], out: &'scope Mutex<W>)
+        where W: Write + Send
+    {
+        self.finish(true);
+
+        let pos = self.game.position().clone();
+        let limits = parse_go(words, pos.get_player());
+        let tablebase = self.options.tablebase_for(pos.get_rules()).unwrap_or_else(|e| {
+            send(out, &format!("info string {}", e));
+            None
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let mut searcher = self.searcher.take().unwrap_or_else(|| {
+            let mut searcher = Searcher::new(pos.clone());
+            searcher.set_hash_size(self.options.hash);
+            searcher
+        });
+
+        searcher.set_position(pos);
+        searcher.set_skill(self.options.skill);
+        searcher.set_contempt(self.options.contempt);
+        searcher.set_params(self.options.params.clone());
+        searcher.set_tablebase(tablebase);
+        searcher.set_book(self.options.book.clone());
+        searcher.set_stop(stop.clone());
+
+        let handle = s.spawn(move || {
+            run_search(&mut searcher, &limits, out);
+            searcher
+        });
+
+        self.running = Some(Running {
+            stop,
+            infinite: limits == Limits::default(),
+            handle,
+        });
+    }
+}
+
+/// Runs the engine protocol, modeled on UCI, over `lines` until `quit` or
+/// the end of input, writing replies to `out`. Searches run in the
+/// background so that `stop` and `isready` are answered while thinking.
+///
+/// Besides the UCI commands `uci`, `isready`, `setoption`, `ucinewgame`,
+/// `position`, `go`, `stop` and `quit`, `d` shows the current position.
+pub fn run_uci<I, W>(tables: &'static Tables, lines: I, out: W)
+    where I: IntoIterator<Item = String>, W: Write + Send
+{
+    let out = Mutex::new(out);
+
+    thread::scope(|s| {
+        let out = &out;
+        let mut engine = Engine {
+            tables,
+            options: UciOptions::default(),
+            game: Game::new(Position::new(tables)),
+            searcher: None,
+            running: None,
+        };
+
+        for line in lines {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            match words.as_slice() {
+                [] => {}
+                ["uci"] => {
+                    send(out, &format!("id name martian_engine {}", env!("CARGO_PKG_VERSION")));
+                    send(out, &format!("id author {}", env!("CARGO_PKG_AUTHORS")));
+
+                    for option in UciOptions::default().describe() {
+                        send(out, &option);
+                    }
+
+                    send(out, "uciok");
+                }
+                ["isready"] => send(out, "readyok"),
+                ["setoption", "name", rest @ ..] => {
+                    engine.finish(true);
+
+                    let (name, value) = match rest.iter().position(|w| *w == "value") {
+                        Some(i) => (rest[..i].join(" "), rest[i + 1..].join(" ")),
+                        None => (rest.join(" "), String::new()),
+                    };
+
+                    match engine.options.set(&name, &value) {
+                        Ok(()) => {
+                            if name.eq_ignore_ascii_case("hash") {
+                                engine.searcher = None;
+                            }
+                        }
+                        Err(e) => send(out, &format!("info string {}", e)),
+                    }
+                }
+                ["ucinewgame"] => {
+                    engine.finish(true);
+                    engine.searcher = None;
+                    engine.game = Game::new(Position::new(tables).with_rules(engine.options.rules));
+                }
+                ["position", rest @ ..] => {
+                    engine.finish(true);
+
+                    match parse_position(rest, engine.tables, &engine.options.rules) {
+                        Ok(game) => engine.game = game,
+                        Err(e) => send(out, &format!("info string {}", e)),
+                    }
+                }
+                ["go", rest @ ..] => engine.go(s, rest, out),
+                ["stop"] => engine.finish(true),
+                ["quit"] => {
+                    engine.finish(true);
+                    return;
+                }
+                ["d"] => {
+                    let pos = engine.game.position();
+                    send(out, &format!("{}\n{}", pos.to_fen(), pos));
+                }
+                _ => send(out, &format!("info string unknown command: {}", line.trim())),
+            }
+        }
+
+        // input ended, so let a limited search send its move
+        let infinite = engine.running.as_ref().is_some_and(|r| r.infinite);
+        engine.finish(infinite);
+    });
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use crate::board::*;
+    use super::*;
+
+    #[test]
+    fn t_parse_go() {
+        let limits = parse_go(&["depth", "3", "nodes", "5000"], false);
+
+        assert_eq!(limits.depth, 3);
+        assert_eq!(limits.nodes, 5000);
+        assert_eq!(limits.time, usize::MAX);
+
+        assert_eq!(parse_go(&["movetime", "250"], true), Limits::time(250));
+        assert_eq!(parse_go(&[], false), Limits::default());
+        assert_eq!(parse_go(&["infinite"], false), Limits::default());
+
+        let clocks = ["p1time", "10000", "p2time", "50000", "p1inc", "100", "p2inc", "100"];
+
+        assert_eq!(parse_go(&clocks, false).time, 10000 / 25 + 50);
+        assert_eq!(parse_go(&clocks, true).time, 50000 / 25 + 50);
+        assert_eq!(parse_go(&["p1time", "300", "p1inc", "1000"], false).time, 150);
+        assert_eq!(parse_go(&["wtime", "10", "btime", "10"], false).time, 1);
+    }
+
+    #[test]
+    fn t_parse_position() {
+        let tables = &TABLES;
+        let rules = RuleSet::STANDARD;
+
+        let game = parse_position(&["startpos"], tables, &rules).unwrap();
+
+        assert_eq!(game.position().board, Board::new());
+
+        let fen = Position::new(tables).to_fen();
+        let mut words = vec!["fen"];
+
+        words.extend(fen.split(' '));
+
+        let game = parse_position(&words, tables, &rules).unwrap();
+
+        assert_eq!(game.position().to_fen(), fen);
+
+        let mov = game.legal_moves()[0];
+        let mut g = game.clone();
+
+        g.do_move(mov);
+
+        let s = g.move_strings()[0].clone();
+        let game = parse_position(&["startpos", "moves", &s], tables, &rules).unwrap();
+
+        assert_eq!(game.moves(), &[mov]);
+
+        assert!(parse_position(&["startpos", "moves", "a1a1"], tables, &rules).is_err());
+        assert!(parse_position(&["fen", "xyz", "0", "1", "0"], tables, &rules).is_err());
+        assert!(parse_position(&["start"], tables, &rules).is_err());
+
+        // a pawn crossing the canal and straight back, which only rules
+        // without no-reversal allow
+        let board = Board::from_desc("3q/4/4/4/2p1/4/4/p3");
+        let words = format!("fen {:016x} 0 1 0 moves {} {}",
+                            board.0, stringify_move((13, 16)), stringify_move((16, 13)));
+        let words = words.split(' ').collect::<Vec<_>>();
+        let other = RuleSet {no_reversal: false, ..rules};
+
+        assert!(parse_position(&words, tables, &rules).is_err());
+        assert_eq!(parse_position(&words, tables, &other).unwrap().position().board, board);
+    }
+
+    #[test]
+    fn t_options() {
+        let mut options = UciOptions::default();
+
+        options.set("Hash", "32").unwrap();
+        options.set("Skill", "3").unwrap();
+        options.set("Contempt", "-5000").unwrap();
+
+        assert_eq!(options.hash, 32);
+        assert_eq!(options.skill, 3);
+        assert_eq!(options.contempt, -MAX_CONTEMPT);
+
+        assert!(options.set("Skill", "high").is_err());
+        assert!(options.set("Ponder", "true").is_err());
+        assert!(options.set("Threads", "2").is_err());
+        assert!(options.set("EvalFile", "/nonexistent/params").is_err());
+
+        assert!(options.set("TablebaseFile", "/nonexistent/tables").is_err());
+        assert!(options.set("BookFile", "/nonexistent/book").is_err());
+
+        options.set("EvalFile", "").unwrap();
+        options.set("TablebaseFile", "<empty>").unwrap();
+
+        assert_eq!(options.params, EvalParams::default());
+        assert_eq!(options.tablebase, None);
+
+        // tables only apply to the rules they were solved for
+        options.tablebase = Some(Arc::new(Tablebase::generate(2)));
+
+        assert!(options.tablebase_for(&RuleSet::STANDARD).is_err());
+        assert!(options.tablebase_for(&TABLEBASE_RULES).unwrap().is_some());
+
+        options.tablebase = None;
+
+        options.set("FieldPromotion", "false").unwrap();
+        options.set("NoReversal", "false").unwrap();
+        options.set("EndWithoutMoves", "true").unwrap();
+        options.set("WinningLead", "5000").unwrap();
+        options.set("NoCaptureLimit", "50").unwrap();
+        options.set("MoveLimit", "300").unwrap();
+        options.set("RepetitionDraw", "true").unwrap();
+
+        assert_eq!(options.rules, RuleSet {
+            field_promotion: false,
+            no_reversal: false,
+            end_without_moves: true,
+            winning_lead: Some(MAX_LEAD),
+            no_capture_limit: Some(50),
+            move_limit: Some(300),
+            repetition_draw: true,
+        });
+
+        options.set("WinningLead", "0").unwrap();
+
+        assert_eq!(options.rules.winning_lead, None);
+        assert!(options.set("NoReversal", "yes").is_err());
+        assert!(options.set("WinningLead", "-1").is_err());
+    }
+
+    #[test]
+    fn t_run_uci() {
+        let tables = &TABLES;
+        let input = [
+            "uci",
+            "setoption name Hash value 1",
+            "isready",
+            "position startpos",
+            "go depth 2",
+            "stop",
+            "position startpos",
+            "go depth 3",
+        ];
+
+        let mut out = Vec::new();
+
+        run_uci(tables, input.iter().map(|s| s.to_string()), &mut out);
+
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+
+        assert!(lines[0].starts_with("id name martian_engine"));
+        assert!(lines.contains(&"uciok"));
+        assert!(lines.contains(&"readyok"));
+        assert_eq!(lines.iter().filter(|l| l.starts_with("bestmove")).count(), 2);
+
+        let info = lines.iter().rev().find(|l| l.starts_with("info depth 3")).unwrap();
+        let pv = info.split(" pv ").nth(1).unwrap();
+        let best = lines.last().unwrap().split_whitespace().nth(1).unwrap();
+
+        assert!(lines.last().unwrap().starts_with("bestmove"));
+        assert_eq!(pv.split(' ').next(), Some(best));
+        assert!(parse_position(&["startpos", "moves", best], tables, &RuleSet::STANDARD).is_ok());
+    }
+}
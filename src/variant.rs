@@ -0,0 +1,113 @@
+pub const DIAGONAL: [(isize, isize); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+pub const ORTHOGONAL: [(isize, isize); 4] = [(-1, 0), (0, -1), (1, 0), (0, 1)];
+pub const ALL_DIRECTIONS: [(isize, isize); 8] = [
+    (-1, 0), (1, 0), (-1, -1), (1, -1), (-1, 1), (1, 1), (0, -1), (0, 1)
+];
+
+/// How one kind of piece moves and what capturing it scores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceDef {
+    pub name: String,
+    /// single steps as (file, rank) deltas, with files counted like square
+    /// indices
+    pub directions: Vec<(isize, isize)>,
+    /// how many steps the piece can slide in one direction
+    pub range: usize,
+    /// points for capturing the piece
+    pub value: i32,
+}
+
+impl PieceDef {
+    pub fn new(name: &str, directions: &[(isize, isize)], range: usize, value: i32) -> Self {
+        Self {
+            name: name.to_string(),
+            directions: directions.to_vec(),
+            range,
+            value,
+        }
+    }
+}
+
+/// A set of rules for the three kinds of piece the board can hold. They keep
+/// their places in the board encoding, the start position and field
+/// promotions: two of the first kind merge into the second, and the first
+/// with the second into the third.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub name: String,
+    /// in the order pawn, drone, queen
+    pub pieces: [PieceDef; 3],
+}
+
+impl Variant {
+    /// The standard rules: pawns step diagonally, drones slide up to two
+    /// squares orthogonally, queens slide any distance in every direction,
+    /// and each is worth its number of bits in the board encoding.
+    pub fn standard() -> Self {
+        Self {
+            name: "standard".to_string(),
+            pieces: [
+                PieceDef::new("pawn", &DIAGONAL, 1, 1),
+                PieceDef::new("drone", &ORTHOGONAL, 2, 2),
+                PieceDef::new("queen", &ALL_DIRECTIONS, 9, 3),
+            ],
+        }
+    }
+
+    /// Checks that every piece moves by single steps in at least one
+    /// distinct direction and can move at least one square.
+    pub fn validate(&self) -> Result<(), String> {
+        for piece in self.pieces.iter() {
+            let dirs = &piece.directions;
+
+            if dirs.is_empty() || piece.range == 0 {
+                return Err(format!("{} cannot move", piece.name));
+            }
+            if let Some(d) = dirs.iter().find(|d| !ALL_DIRECTIONS.contains(d)) {
+                return Err(format!("{} has a bad direction {:?}", piece.name, d));
+            }
+            if (1..dirs.len()).any(|i| dirs[..i].contains(&dirs[i])) {
+                return Err(format!("{} repeats a direction", piece.name));
+            }
+            if piece.value < 0 {
+                return Err(format!("{} has a negative value", piece.name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The point values of the pieces, in the order of `pieces`.
+    pub fn values(&self) -> [i32; 3] {
+        [self.pieces[0].value, self.pieces[1].value, self.pieces[2].value]
+    }
+}
+
+#[allow(unused_imports)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_validate() {
+        let standard = Variant::standard();
+
+        assert_eq!(standard.validate(), Ok(()));
+        assert_eq!(standard.values(), [1, 2, 3]);
+
+        let mut variant = standard.clone();
+        variant.pieces[1].range = 0;
+        assert!(variant.validate().is_err());
+
+        let mut variant = standard.clone();
+        variant.pieces[0].directions.push((2, 1));
+        assert!(variant.validate().is_err());
+
+        let mut variant = standard.clone();
+        variant.pieces[2].directions.push((0, 1));
+        assert!(variant.validate().is_err());
+
+        let mut variant = standard;
+        variant.pieces[0].value = -1;
+        assert!(variant.validate().is_err());
+    }
+}
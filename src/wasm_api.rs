@@ -2,17 +2,20 @@ use crate::gen_tables::*;
 use crate::board::*;
 use crate::position::*;
 use crate::search::*;
+use crate::eval::*;
 
 use wasm_bindgen::prelude::*;
 use lazy_static::*;
-use std::sync::Arc;
+use std::sync::Mutex;
 
 struct Api {
-    position: Position<'static>,
+    position: Position,
     best_move: Board,
     best_score: i32,
     p1_score: i32,
     p2_score: i32,
+    skill: usize,
+    params: EvalParams,
 }
 
 impl Api {
@@ -23,6 +26,8 @@ impl Api {
             best_score: 0,
             p1_score: 0,
             p2_score: 0,
+            skill: MAX_SKILL,
+            params: EvalParams::default(),
         }
     }
 
@@ -38,70 +43,127 @@ impl Api {
 }
 
 lazy_static! {
-    static ref TABLES: Arc<Tables> = Arc::new(Tables::new());
-    static ref API: Arc<Api> = Arc::new(Api::new());
+    static ref API: Mutex<Api> = Mutex::new(Api::new());
+}
+
+/// The engine state shared by the calls from the page, which never run
+/// concurrently, so the lock is never contended.
+fn api() -> std::sync::MutexGuard<'static, Api> {
+    API.lock().unwrap_or_else(|e| e.into_inner())
 }
 
 #[wasm_bindgen]
 pub fn reset() {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let mut api = api();
 
-    *api = Api::new()
+    let skill = api.skill;
+    let params = api.params.clone();
+
+    *api = Api::new();
+    api.skill = skill;
+    api.params = params;
 }
 
 #[wasm_bindgen]
 pub fn set_position(pos: JsPosition) {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let mut api = api();
 
     api.position = Position::from_js(&TABLES, pos);
 }
 
 #[wasm_bindgen]
 pub fn get_position() -> JsPosition {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     api.position.to_js()
 }
 
 #[wasm_bindgen]
 pub fn get_best_move() -> JsBoard {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     api.best_move.to_js()
 }
 
 #[wasm_bindgen]
 pub fn get_best_score() -> i32 {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     api.best_score
 }
 
 #[wasm_bindgen]
 pub fn get_p1_score() -> i32 {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     api.p1_score
 }
 
 #[wasm_bindgen]
 pub fn get_p2_score() -> i32 {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     api.p2_score
 }
 
+#[wasm_bindgen]
+pub fn set_skill(level: usize) {
+    let mut api = api();
+
+    api.skill = level.min(MAX_SKILL);
+}
+
+#[wasm_bindgen]
+pub fn get_skill() -> usize {
+    let api = api();
+
+    api.skill
+}
+
+#[wasm_bindgen]
+pub fn get_max_skill() -> usize {
+    MAX_SKILL
+}
+
+#[wasm_bindgen]
+pub fn get_skill_rating(level: usize) -> i32 {
+    Skill::level(level).rating
+}
+
+/// Replaces the evaluation weights with ones in the parameter file format,
+/// returning false and keeping the current weights if `text` is invalid.
+#[wasm_bindgen]
+pub fn set_eval_params(text: String) -> bool {
+    let mut api = api();
+
+    match text.parse() {
+        Ok(params) => {
+            api.params = params;
+            true
+        }
+        Err(_) => false
+    }
+}
+
+#[wasm_bindgen]
+pub fn get_eval_params() -> String {
+    let api = api();
+
+    api.params.to_string()
+}
+
+/// A table of the evaluation terms of the current position, from the point
+/// of view of the side to move.
+#[wasm_bindgen]
+pub fn get_eval_explain() -> String {
+    let api = api();
+
+    api.position.eval_explain_with(&api.params).to_string()
+}
+
 #[wasm_bindgen]
 pub fn move_is_valid(mov: JsBoard) -> bool {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     let mut moves = Vec::new();
 
@@ -111,8 +173,7 @@ pub fn move_is_valid(mov: JsBoard) -> bool {
 
 #[wasm_bindgen]
 pub fn do_move(mov: JsBoard) {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let mut api = api();
 
     api.position.do_move(Board::from_js(mov));
     api.update_scores();
@@ -120,8 +181,7 @@ pub fn do_move(mov: JsBoard) {
 
 #[wasm_bindgen]
 pub fn do_num_move(sq1: usize, sq2: usize) -> bool {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let mut api = api();
 
     let out = api.position.do_num_move(sq1, sq2);
     api.update_scores();
@@ -131,8 +191,7 @@ pub fn do_num_move(sq1: usize, sq2: usize) -> bool {
 
 #[wasm_bindgen]
 pub fn do_string_move(mov: String) -> bool {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let mut api = api();
 
     let out = api.position.do_string_move(&mov);
     api.update_scores();
@@ -142,26 +201,26 @@ pub fn do_string_move(mov: String) -> bool {
 
 #[wasm_bindgen]
 pub fn get_string_move() -> String {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     stringify_move(api.position.get_move())
 }
 
 #[wasm_bindgen]
 pub fn get_piece_moves(sq: usize) -> i32 {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let api = api();
 
     api.position.gen_piece_moves(sq) as i32
 }
 
 #[wasm_bindgen]
 pub fn search(time: i32) -> bool {
-    let mut tmp = API.clone();
-    let api = unsafe{Arc::get_mut_unchecked(&mut tmp)};
+    let mut api = api();
     let mut searcher = Searcher::new(api.position.clone());
 
+    searcher.set_skill(api.skill);
+    searcher.set_params(api.params.clone());
+
     let (mov, score) = searcher.ab_search(time as usize);
 
     if let Some(mov) = mov {
@@ -166,7 +166,22 @@ function set_square_click_events() {
     }
 }
 
+function init_skill_select() {
+    var out = "";
+
+    for (var level = 0; level <= engine.get_max_skill(); level++) {
+        out += '<option value="' + level + '">Level ' + level +
+            ' (~' + engine.get_skill_rating(level) + ')</option>';
+    }
+
+    $('#skill')
+        .html(out)
+        .val(engine.get_skill())
+        .change(() => engine.set_skill(parseInt($('#skill').val())));
+}
+
 function run() {
+    init_skill_select();
     render_engine_board();
     set_square_click_events();
 }